@@ -1,133 +1,514 @@
-use std::io::prelude::*;
+extern crate intcode;
+use intcode::{HaltError, Machine, Memory};
+use std::fmt;
 
-fn process(input_prog: &Vec<usize>) -> Vec<usize> {
-    let mut prog = input_prog.to_vec();
-    let mut prog_counter: usize = 0;
+/// Runs the program on the full Intcode instruction set and returns its
+/// outputs and final memory. Touching an address past the end of the
+/// program is a fault, since day 2 programs never grow beyond it.
+fn run(input_prog: &[i64], inputs: &[i64]) -> Result<(Vec<i64>, Vec<i64>), HaltError> {
+    let mut memory = Memory::new(input_prog.to_vec());
+    memory.set_max_size(Some(input_prog.len()));
+    intcode::run_to_halt(Machine::with_memory(memory), inputs)
+}
 
-    const ADD: usize = 1;
-    const MULTIPLY: usize = 2;
-    const HALT: usize = 99;
-
-    while {
-        let opcode = prog[prog_counter];
-        match opcode {
-            ADD => {
-                let operand1 = prog[prog_counter + 1];
-                let operand2 = prog[prog_counter + 2];
-                let dest = prog[prog_counter + 3];
-                prog[dest] = prog[operand1] + prog[operand2];
-            }
-            MULTIPLY => {
-                let operand1 = prog[prog_counter + 1];
-                let operand2 = prog[prog_counter + 2];
-                let dest = prog[prog_counter + 3];
-                prog[dest] = prog[operand1] * prog[operand2];
-            }
-            HALT => (), // do nothing, we'll break later
-            _ => panic!("unexpected opcode"),
+fn process(input_prog: &[i64]) -> Result<Vec<i64>, HaltError> {
+    run(input_prog, &[]).map(|(_, memory)| memory)
+}
+
+/// A memory cell's value as `noun * n + verb * v + constant` for a program
+/// with noun `n` and verb `v` patched in.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Linear {
+    noun: i64,
+    verb: i64,
+    constant: i64,
+}
+
+impl Linear {
+    fn constant(value: i64) -> Linear {
+        Linear {
+            noun: 0,
+            verb: 0,
+            constant: value,
         }
+    }
 
-        prog_counter += 4;
+    fn as_constant(&self) -> Option<i64> {
+        if self.noun == 0 && self.verb == 0 {
+            Some(self.constant)
+        } else {
+            None
+        }
+    }
+
+    fn add(&self, other: &Linear) -> Option<Linear> {
+        Some(Linear {
+            noun: self.noun.checked_add(other.noun)?,
+            verb: self.verb.checked_add(other.verb)?,
+            constant: self.constant.checked_add(other.constant)?,
+        })
+    }
+
+    // Only linear while one side is a constant.
+    fn multiply(&self, other: &Linear) -> Option<Linear> {
+        let (k, x) = match (self.as_constant(), other.as_constant()) {
+            (Some(k), _) => (k, other),
+            (_, Some(k)) => (k, self),
+            _ => return None,
+        };
+        Some(Linear {
+            noun: x.noun.checked_mul(k)?,
+            verb: x.verb.checked_mul(k)?,
+            constant: x.constant.checked_mul(k)?,
+        })
+    }
+
+    fn eval(&self, noun: i64, verb: i64) -> i128 {
+        self.noun as i128 * noun as i128 + self.verb as i128 * verb as i128 + self.constant as i128
+    }
+
+    /// A bound on its magnitude for any noun and verb in 0..100.
+    fn max_abs(&self) -> Option<i64> {
+        let bound = self.constant.unsigned_abs() as u128
+            + 99 * (self.noun.unsigned_abs() as u128 + self.verb.unsigned_abs() as u128);
+        if bound <= i64::MAX as u128 {
+            Some(bound as i64)
+        } else {
+            None
+        }
+    }
+}
 
-        opcode != HALT
-    } {}
+impl fmt::Display for Linear {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} * noun + {} * verb + {}",
+            self.noun, self.verb, self.constant
+        )
+    }
+}
 
-    prog.to_vec()
+/// A memory cell during symbolic execution.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Cell {
+    Known(Linear),
+    /// Only known to stay within `bound` either side of 0, e.g. a value
+    /// read through a noun- or verb-dependent address.
+    Unknown {
+        bound: i64,
+    },
 }
 
-fn find_noun_verb(input_prog: &Vec<usize>) -> (usize, usize) {
-    let mut noun_verb = (0, 0);
-    for noun in 0..100 {
-        for verb in 0..100 {
-            let mut patched = input_prog.to_vec();
-            patched[1] = noun;
-            patched[2] = verb;
-            let result = process(&patched);
-            if result[0] == 19690720 {
-                noun_verb = (noun, verb);
+impl Cell {
+    fn bound(&self) -> Option<i64> {
+        match *self {
+            Cell::Known(ref x) => x.max_abs(),
+            Cell::Unknown { bound } => Some(bound),
+        }
+    }
+}
+
+/// Address 0 as an expression in the noun and verb, together with the
+/// conditions under which the program actually gets there.
+#[derive(Clone, PartialEq, Debug)]
+struct ClosedForm {
+    output: Linear,
+    /// Addresses read through noun- or verb-dependent pointers, which
+    /// fault unless they land inside the program.
+    reads: Vec<Linear>,
+    /// Every value the program computes, which faults on overflowing i64.
+    results: Vec<Linear>,
+}
+
+impl ClosedForm {
+    /// Whether running with this noun and verb halts without a fault.
+    fn runs(&self, noun: i64, verb: i64, len: usize) -> bool {
+        let fits = |x: &Linear| {
+            let v = x.eval(noun, verb);
+            v >= i64::MIN as i128 && v <= i64::MAX as i128
+        };
+        self.reads.iter().all(|x| {
+            let addr = x.eval(noun, verb);
+            addr >= 0 && addr < len as i128
+        }) && self.results.iter().all(fits)
+    }
+}
+
+/// Runs the program with the noun and verb left as unknowns and returns
+/// address 0 as an expression in them. Gives `None` when that isn't
+/// linear, when an opcode or write address depends on the noun or verb,
+/// when an overflow can't be ruled out for unknown values, or when the
+/// program uses anything but position mode adds and multiplies.
+fn symbolic_output(input_prog: &[i64]) -> Option<ClosedForm> {
+    let mut cells: Vec<Cell> = input_prog
+        .iter()
+        .map(|&x| Cell::Known(Linear::constant(x)))
+        .collect();
+    if cells.len() < 3 {
+        return None;
+    }
+    cells[1] = Cell::Known(Linear {
+        noun: 1,
+        verb: 0,
+        constant: 0,
+    });
+    cells[2] = Cell::Known(Linear {
+        noun: 0,
+        verb: 1,
+        constant: 0,
+    });
+    let mut reads = Vec::new();
+    let mut results = Vec::new();
+
+    let constant_at = |cells: &Vec<Cell>, addr: usize| -> Option<usize> {
+        match *cells.get(addr)? {
+            Cell::Known(ref x) if x.as_constant()? >= 0 => Some(x.constant as usize),
+            _ => None,
+        }
+    };
+    let mut prog_counter: usize = 0;
+    loop {
+        let opcode = constant_at(&cells, prog_counter)?;
+        if opcode == 99 {
+            break;
+        }
+        if opcode != 1 && opcode != 2 {
+            return None;
+        }
+        let mut operands = [Cell::Unknown { bound: 0 }; 2];
+        for (i, operand) in operands.iter_mut().enumerate() {
+            *operand = match *cells.get(prog_counter + 1 + i)? {
+                Cell::Known(ref addr) if addr.as_constant().is_some() => {
+                    *cells.get(constant_at(&cells, prog_counter + 1 + i)?)?
+                }
+                Cell::Known(ref addr) => {
+                    // Could be any word of the program, if it doesn't fault.
+                    reads.push(*addr);
+                    let bounds: Option<Vec<i64>> = cells.iter().map(Cell::bound).collect();
+                    let bound = bounds?.into_iter().max()?;
+                    Cell::Unknown { bound }
+                }
+                Cell::Unknown { .. } => return None,
+            };
+        }
+        let dest = constant_at(&cells, prog_counter + 3)?;
+        let linear = match (operands[0], operands[1]) {
+            (Cell::Known(a), Cell::Known(b)) if opcode == 1 => a.add(&b),
+            (Cell::Known(a), Cell::Known(b)) => a.multiply(&b),
+            _ => None,
+        };
+        let result = match linear {
+            Some(x) => {
+                results.push(x);
+                Cell::Known(x)
+            }
+            None => {
+                let (a, b) = (operands[0].bound()?, operands[1].bound()?);
+                let bound = if opcode == 1 {
+                    a.checked_add(b)?
+                } else {
+                    a.checked_mul(b)?
+                };
+                Cell::Unknown { bound }
             }
+        };
+        *cells.get_mut(dest)? = result;
+        prog_counter += 4;
+    }
+    match cells[0] {
+        Cell::Known(output) => Some(ClosedForm {
+            output,
+            reads,
+            results,
+        }),
+        Cell::Unknown { .. } => None,
+    }
+}
+
+/// Noun and verb pairs, each in 0..100, that leave a given value at
+/// address 0.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Solutions {
+    NoSolution,
+    One(usize, usize),
+    /// At least two; the first two in order of noun, then verb.
+    Many((usize, usize), (usize, usize)),
+}
+
+impl Solutions {
+    fn from_pairs<I: Iterator<Item = (usize, usize)>>(mut pairs: I) -> Solutions {
+        match (pairs.next(), pairs.next()) {
+            (None, _) => Solutions::NoSolution,
+            (Some((noun, verb)), None) => Solutions::One(noun, verb),
+            (Some(first), Some(second)) => Solutions::Many(first, second),
         }
     }
+}
 
-    noun_verb
+fn solve_linear(form: &ClosedForm, target: i64, len: usize) -> Solutions {
+    Solutions::from_pairs((0..100).flat_map(move |noun| {
+        (0..100)
+            .filter(move |&verb| {
+                form.output.eval(noun, verb) == target as i128 && form.runs(noun, verb, len)
+            })
+            .map(move |verb| (noun as usize, verb as usize))
+    }))
+}
+
+fn search_noun_verb(input_prog: &[i64], target: i64) -> Solutions {
+    if input_prog.len() < 3 {
+        return Solutions::NoSolution;
+    }
+    Solutions::from_pairs((0..100).flat_map(move |noun| {
+        (0..100)
+            .filter(move |&verb| {
+                let mut patched = input_prog.to_vec();
+                patched[1] = noun;
+                patched[2] = verb;
+                process(&patched).is_ok_and(|memory| memory[0] == target)
+            })
+            .map(move |verb| (noun as usize, verb as usize))
+    }))
+}
+
+/// Finds the noun and verb giving `target` at address 0, solving the
+/// program's closed form where there is one and running every pair where
+/// there isn't. Pairs the program faults on don't count.
+fn find_noun_verb(input_prog: &[i64], target: i64) -> Solutions {
+    match symbolic_output(input_prog) {
+        Some(form) => solve_linear(&form, target, input_prog.len()),
+        None => search_noun_verb(input_prog, target),
+    }
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let mut patches = intcode::take_patch_args(&mut args).unwrap_or_else(|e| panic!("{}", e));
     if args.len() != 2 {
-        panic!("Provide one argument with path to the program");
-    }
-
-    let file = std::fs::File::open(&args[1]).unwrap();
-    let mut reader = std::io::BufReader::new(file);
-    let mut file_string = String::new();
-    reader
-        .read_to_string(&mut file_string)
-        .expect("Unable to read file");
-
-    // load program
-    let no_whitespace_str: String = file_string
-        .split_whitespace()
-        .collect::<Vec<&str>>()
-        .join("");
-    let int_strs: Vec<&str> = no_whitespace_str.split(",").collect();
-    let program: Vec<usize> = int_strs
-        .into_iter()
-        .map(|x| x.parse::<usize>().unwrap())
-        .collect();
+        panic!(
+            "Provide one argument with path to the program, optionally followed by \
+             --patch <addr=value,...> and/or --patch-file <file> for part 1 (default 1=12,2=2)"
+        );
+    }
+    if patches.is_empty() {
+        patches = intcode::parse_patches("1=12,2=2").unwrap();
+    }
 
-    // patch program
+    let program = intcode::load_program(&args[1]);
     let mut patched_program = program.to_vec();
-    patched_program[1] = 12;
-    patched_program[2] = 2;
+    intcode::apply_patches(&mut patched_program, &patches).unwrap_or_else(|e| panic!("{}", e));
 
+    println!("{}", intcode::describe_patches(&patches));
     println!("before program run {:?}", patched_program);
-    let result = process(&patched_program);
-    println!("after program run {:?}", result);
+    match process(&patched_program) {
+        Ok(result) => println!("after program run {:?}", result),
+        Err(e) => println!("program failed: {}", e),
+    }
 
-    let noun_verb = find_noun_verb(&program);
-    let noun_verb_result = noun_verb.0 * 100 + noun_verb.1;
-    println!("Noun: {}, Verb: {}, 100xN+V: {}", noun_verb.0, noun_verb.1, noun_verb_result);
+    match symbolic_output(&program) {
+        Some(form) => println!("Address 0 = {}", form.output),
+        None => println!("Address 0 is not linear in noun and verb, searching"),
+    }
+    match find_noun_verb(&program, 19690720) {
+        Solutions::One(noun, verb) => {
+            println!(
+                "Noun: {}, Verb: {}, 100xN+V: {}",
+                noun,
+                verb,
+                noun * 100 + verb
+            )
+        }
+        Solutions::Many(first, second) => {
+            println!("Several solutions, including {:?} and {:?}", first, second)
+        }
+        Solutions::NoSolution => println!("No solution"),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use process;
+    use intcode::VmError;
+    use {
+        find_noun_verb, process, run, search_noun_verb, solve_linear, symbolic_output, ClosedForm,
+        HaltError, Linear, Solutions,
+    };
+
+    fn load_input() -> Vec<i64> {
+        intcode::load_program("input")
+    }
 
     #[test]
     fn test1() {
         let test_input = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
         let test_result = process(&test_input);
-        let test_answer = vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50];
+        let test_answer = Ok(vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
         assert_eq!(test_result, test_answer);
     }
     #[test]
     fn test2() {
         let test_input = vec![1, 0, 0, 0, 99];
         let test_result = process(&test_input);
-        let test_answer = vec![2, 0, 0, 0, 99];
+        let test_answer = Ok(vec![2, 0, 0, 0, 99]);
         assert_eq!(test_result, test_answer);
     }
     #[test]
     fn test3() {
         let test_input = vec![2, 3, 0, 3, 99];
         let test_result = process(&test_input);
-        let test_answer = vec![2, 3, 0, 6, 99];
+        let test_answer = Ok(vec![2, 3, 0, 6, 99]);
         assert_eq!(test_result, test_answer);
     }
     #[test]
     fn test4() {
         let test_input = vec![2, 4, 4, 5, 99, 0];
         let test_result = process(&test_input);
-        let test_answer = vec![2, 4, 4, 5, 99, 9801];
+        let test_answer = Ok(vec![2, 4, 4, 5, 99, 9801]);
         assert_eq!(test_result, test_answer);
     }
     #[test]
     fn test5() {
         let test_input = vec![1, 1, 1, 4, 99, 5, 6, 0, 99];
         let test_result = process(&test_input);
-        let test_answer = vec![30, 1, 1, 4, 2, 5, 6, 0, 99];
+        let test_answer = Ok(vec![30, 1, 1, 4, 2, 5, 6, 0, 99]);
         assert_eq!(test_result, test_answer);
     }
+    #[test]
+    fn conformance() {
+        // Day 9's cases use memory past the end of the program.
+        let res = intcode::check_conformance(intcode::Level::Day5, |prog, inputs| {
+            run(prog, inputs).map_err(|e| e.to_string())
+        });
+        assert!(res.is_ok(), "{:?}", res);
+    }
+    #[test]
+    fn faults() {
+        assert_eq!(
+            process(&[1, 0, 0, 0, 42, 99]),
+            Err(HaltError::Fault(VmError::InvalidOpcode {
+                pc: 4,
+                instr: 42
+            }))
+        );
+        // Truncated in the middle of an instruction, and running off the end.
+        assert_eq!(
+            process(&[1, 0, 0]),
+            Err(HaltError::Fault(VmError::AddressTooLarge {
+                pc: 0,
+                instr: 1,
+                addr: 3
+            }))
+        );
+        assert_eq!(
+            process(&[1, 0, 0, 0]).unwrap_err().to_string(),
+            "address 4 beyond memory limit at pc 4 (instruction 0)"
+        );
+        assert_eq!(
+            process(&[1, 0, 7, 0, 99]).unwrap_err().to_string(),
+            "address 7 beyond memory limit at pc 0 (instruction 1)"
+        );
+        assert_eq!(process(&[3, 0, 99]), Err(HaltError::NeedsInput { pc: 0 }));
+        assert_eq!(
+            process(&[1105, 1, 0]),
+            Err(HaltError::InfiniteLoop { pc: 0 })
+        );
+    }
+    #[test]
+    fn patched_input() {
+        let mut program = load_input();
+        intcode::apply_patches(&mut program, &intcode::parse_patches("1=12,2=2").unwrap()).unwrap();
+        assert_eq!(process(&program).unwrap()[0], 4138658);
+    }
+    #[test]
+    fn symbolic_matches_search() {
+        let program = load_input();
+        let form = symbolic_output(&program).unwrap();
+        assert_eq!(
+            form.output,
+            Linear {
+                noun: 259200,
+                verb: 1,
+                constant: 1028256
+            }
+        );
+        assert_eq!(find_noun_verb(&program, 19690720), Solutions::One(72, 64));
+        assert_eq!(search_noun_verb(&program, 19690720), Solutions::One(72, 64));
+        assert_eq!(find_noun_verb(&program, 19690820), Solutions::NoSolution);
+    }
+    #[test]
+    fn linear_solutions() {
+        // The first instruction uses noun and verb as addresses, but its
+        // result is overwritten with (noun + verb) * 3.
+        let program = vec![1, 0, 0, 0, 1, 1, 2, 0, 2, 0, 13, 0, 99, 3];
+        assert_eq!(
+            symbolic_output(&program).map(|form| form.output),
+            Some(Linear {
+                noun: 3,
+                verb: 3,
+                constant: 0
+            })
+        );
+        assert_eq!(find_noun_verb(&program, 0), Solutions::One(0, 0));
+        assert_eq!(find_noun_verb(&program, 6), Solutions::Many((0, 2), (1, 1)));
+        assert_eq!(find_noun_verb(&program, 7), Solutions::NoSolution);
+        assert_eq!(find_noun_verb(&program, 600), Solutions::NoSolution);
+        // Reading address noun or verb faults once either is past the end.
+        assert_eq!(find_noun_verb(&program, 150), Solutions::NoSolution);
+    }
+    #[test]
+    fn closed_form_agrees_with_search() {
+        // Address 3 gets noun * K, which overflows for nouns above 50,
+        // before address 0 gets noun + verb.
+        let mut overflows = vec![1, 0, 0, 3, 2, 1, 13, 3, 1, 1, 2, 0, 99, i64::MAX / 50];
+        overflows.resize(100, 0);
+        let cases: Vec<(Vec<i64>, i64)> = vec![
+            (load_input(), 19690720),
+            (vec![1, 0, 0, 0, 1, 1, 2, 0, 2, 0, 13, 0, 99, 3], 6),
+            (vec![1, 0, 0, 0, 1, 1, 2, 0, 2, 0, 13, 0, 99, 3], 150),
+            (overflows.clone(), 150),
+            (overflows, 50),
+            (vec![1, 0], 0),
+        ];
+        for (program, target) in cases {
+            assert_eq!(
+                find_noun_verb(&program, target),
+                search_noun_verb(&program, target),
+                "{:?} for {}",
+                program,
+                target
+            );
+        }
+
+        let huge = ClosedForm {
+            output: Linear {
+                noun: i64::MAX / 2,
+                verb: i64::MAX / 2,
+                constant: 0,
+            },
+            reads: Vec::new(),
+            results: Vec::new(),
+        };
+        assert_eq!(
+            solve_linear(&huge, i64::MAX - 1, 3),
+            Solutions::Many((0, 2), (1, 1))
+        );
+    }
+    #[test]
+    fn non_linear_falls_back_to_search() {
+        // Address 0 ends up as noun * verb.
+        let mut program = vec![1, 0, 0, 0, 2, 1, 2, 0, 99];
+        program.resize(100, 0);
+        assert_eq!(symbolic_output(&program), None);
+        assert_eq!(
+            find_noun_verb(&program, 12),
+            Solutions::Many((1, 12), (2, 6))
+        );
+        assert_eq!(find_noun_verb(&program, 9801), Solutions::One(99, 99));
+        assert_eq!(find_noun_verb(&program, 9802), Solutions::NoSolution);
 
+        // The second instruction stores the noun as the third's write
+        // address.
+        let program = vec![1, 0, 0, 0, 1, 1, 13, 11, 1, 0, 0, 0, 99, 0];
+        assert_eq!(symbolic_output(&program), None);
+    }
 }
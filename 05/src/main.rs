@@ -1,220 +1,190 @@
-use std::io::{self, BufRead, Read};
-
-#[derive(Copy, Clone, PartialEq)]
-enum Opcode {
-    Add = 1,
-    Multiply = 2,
-    Input = 3,
-    Output = 4,
-    JumpTrue = 5,
-    JumpFalse = 6,
-    LessThan = 7,
-    Equals = 8,
-    Halt = 99,
+extern crate intcode;
+use intcode::{HaltError, LineInput, Machine, StdoutOutput};
+use std::io::Read;
+
+/// Runs the program to completion on the same machine as the later days,
+/// relative mode and memory past the end of the program included, and
+/// returns its outputs and final memory.
+fn run(input_prog: Vec<i64>, inputs: &[i64]) -> Result<(Vec<i64>, Vec<i64>), HaltError> {
+    intcode::run_to_halt(Machine::new(input_prog), inputs)
 }
 
-impl Opcode {
-    fn from_i32(value: i32) -> Result<Opcode, String> {
-        match value {
-            1 => Ok(Opcode::Add),
-            2 => Ok(Opcode::Multiply),
-            3 => Ok(Opcode::Input),
-            4 => Ok(Opcode::Output),
-            5 => Ok(Opcode::JumpTrue),
-            6 => Ok(Opcode::JumpFalse),
-            7 => Ok(Opcode::LessThan),
-            8 => Ok(Opcode::Equals),
-            99 => Ok(Opcode::Halt),
-            _ => Err(format!("Invalid Opcode: {}", value)),
+/// What the diagnostic program's outputs say: one output per test, 0 when
+/// it passed, then the diagnostic code.
+#[derive(Clone, PartialEq, Debug)]
+enum Diagnosis {
+    Passed {
+        tests: usize,
+        code: i64,
+    },
+    /// Failing tests by their position among the outputs, with the value
+    /// each one output.
+    Failed {
+        failures: Vec<(usize, i64)>,
+        code: i64,
+    },
+    NoOutput,
+}
+
+fn diagnose(outputs: &[i64]) -> Diagnosis {
+    match outputs.split_last() {
+        None => Diagnosis::NoOutput,
+        Some((&code, tests)) => {
+            let failures: Vec<(usize, i64)> = tests
+                .iter()
+                .cloned()
+                .enumerate()
+                .filter(|&(_, x)| x != 0)
+                .collect();
+            if failures.is_empty() {
+                Diagnosis::Passed {
+                    tests: tests.len(),
+                    code,
+                }
+            } else {
+                Diagnosis::Failed { failures, code }
+            }
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
-enum OperandMode {
-    POSITION = 0,
-    IMMEDIATE = 1,
+/// Runs the diagnostic program for one system ID and returns everything it
+/// output.
+fn run_diagnostics(input_prog: Vec<i64>, system_id: i64) -> Result<Vec<i64>, HaltError> {
+    run(input_prog, &[system_id]).map(|(outputs, _)| outputs)
 }
 
-impl OperandMode {
-    fn from_i32(value: i32) -> Result<OperandMode, String> {
-        match value {
-            0 => Ok(OperandMode::POSITION),
-            1 => Ok(OperandMode::IMMEDIATE),
-            _ => Err(format!("Invalid OperandMode: {}", value)),
-        }
+fn process(input_prog: Vec<i64>) {
+    let mut machine = Machine::new(input_prog);
+    if let Err(e) = machine.run(&mut LineInput::stdin(), &mut StdoutOutput) {
+        eprintln!("{}", e);
     }
+    println!("HALTING");
 }
 
-fn operand_modes(x: i32) -> [OperandMode; 3] {
-    let mut res: [OperandMode; 3] = [OperandMode::POSITION; 3];
-
-    let mut x_div = x;
-    res[0] = OperandMode::from_i32(x_div % 10).expect("");
-    x_div = x_div / 10;
-    res[1] = OperandMode::from_i32(x_div % 10).expect("");
-    x_div = x_div / 10;
-    res[2] = OperandMode::from_i32(x_div % 10).expect("");
-
-    res
+fn read_file(path: &str) -> String {
+    let file = std::fs::File::open(path).unwrap();
+    let mut reader = std::io::BufReader::new(file);
+    let mut file_string = String::new();
+    reader
+        .read_to_string(&mut file_string)
+        .expect("Unable to read file");
+    file_string
 }
 
-enum ParsedInstruction {
-    Add { op1: i32, op2: i32, dest: usize },
-    Multiply { op1: i32, op2: i32, dest: usize },
-    Input { dest: usize },
-    Output { src: usize },
-    JumpTrue { test: i32, jump_dest: usize },
-    JumpFalse { test: i32, jump_dest: usize },
-    LessThan { op1: i32, op2: i32, dest: usize },
-    Equals { op1: i32, op2: i32, dest: usize },
-    Halt,
-}
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let system_id = match args.len() {
+        2 => None,
+        3 => Some(args[2].clone()),
+        4 if args[2] == "--id-file" => Some(read_file(&args[3])),
+        _ => panic!(
+            "Provide one argument with path to the program, optionally followed by a system ID \
+             or --id-file <file>; without one the program is run interactively"
+        ),
+    };
 
-fn load_operands<'a>(
-    op: Opcode,
-    op_modes: [OperandMode; 3],
-    iter: &mut usize,
-    prog: &Vec<i32>,
-) -> ParsedInstruction {
-    let mut parse_operand = |i| -> i32 {
-        let op = if i == OperandMode::POSITION {
-            prog[prog[*iter] as usize]
-        } else {
-            prog[*iter]
-        };
-        *iter += 1;
-        op
+    // load program
+    let file_string = read_file(&args[1]);
+    let program = intcode::parse_program(&file_string);
+
+    let system_id: i64 = match system_id {
+        None => return process(program),
+        Some(id) => id
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("Bad system ID {:?}", id)),
     };
 
-    match op {
-        Opcode::Add => ParsedInstruction::Add {
-            op1: parse_operand(op_modes[0]),
-            op2: parse_operand(op_modes[1]),
-            dest: parse_operand(OperandMode::IMMEDIATE) as usize,
-        },
-        Opcode::Multiply => ParsedInstruction::Multiply {
-            op1: parse_operand(op_modes[0]),
-            op2: parse_operand(op_modes[1]),
-            dest: parse_operand(OperandMode::IMMEDIATE) as usize,
-        },
-        Opcode::Input => ParsedInstruction::Input {
-            dest: parse_operand(OperandMode::IMMEDIATE) as usize,
-        },
-        Opcode::Output => ParsedInstruction::Output {
-            src: parse_operand(OperandMode::IMMEDIATE) as usize,
-        },
-        Opcode::JumpTrue => ParsedInstruction::JumpTrue {
-            test: parse_operand(op_modes[0]),
-            jump_dest: parse_operand(op_modes[1]) as usize,
-        },
-        Opcode::JumpFalse => ParsedInstruction::JumpFalse {
-            test: parse_operand(op_modes[0]),
-            jump_dest: parse_operand(op_modes[1]) as usize,
-        },
-        Opcode::LessThan => ParsedInstruction::LessThan {
-            op1: parse_operand(op_modes[0]),
-            op2: parse_operand(op_modes[1]),
-            dest: parse_operand(OperandMode::IMMEDIATE) as usize,
-        },
-        Opcode::Equals => ParsedInstruction::Equals {
-            op1: parse_operand(op_modes[0]),
-            op2: parse_operand(op_modes[1]),
-            dest: parse_operand(OperandMode::IMMEDIATE) as usize,
-        },
-        Opcode::Halt => ParsedInstruction::Halt,
+    let outputs = run_diagnostics(program, system_id).unwrap_or_else(|e| {
+        println!("Diagnostics failed: {}", e);
+        std::process::exit(1)
+    });
+    println!("System {} outputs {:?}", system_id, outputs);
+    match diagnose(&outputs) {
+        Diagnosis::Passed { tests, code } => {
+            println!("{} tests passed, diagnostic code {}", tests, code)
+        }
+        Diagnosis::Failed { failures, code } => {
+            for (position, value) in failures {
+                println!("Test at output {} failed with {}", position, value);
+            }
+            println!("Diagnostic code {}", code);
+            std::process::exit(1);
+        }
+        Diagnosis::NoOutput => {
+            println!("No output");
+            std::process::exit(1);
+        }
     }
 }
 
-fn parse_next_instr<'a>(iter: &mut usize, prog: &Vec<i32>) -> ParsedInstruction {
-    let combined_opcode = prog[*iter];
-    *iter += 1;
-    let opcode_int = combined_opcode % 100;
-    let operand_modes_int = combined_opcode / 100;
-    let opcode = Opcode::from_i32(opcode_int).unwrap();
-    let operand_modes = operand_modes(operand_modes_int);
+#[cfg(test)]
+mod tests {
+    use intcode::{check_conformance, Level, VmError};
+    use {diagnose, run, run_diagnostics, Diagnosis, HaltError};
 
-    load_operands(opcode, operand_modes, iter, prog)
-}
+    #[test]
+    fn conformance() {
+        let res = check_conformance(Level::Day9, |prog, inputs| {
+            run(prog.to_vec(), inputs).map_err(|e| e.to_string())
+        });
+        assert!(res.is_ok(), "{:?}", res);
+    }
 
-fn process(mut input_prog: Vec<i32>) {
-    let mut pc: usize = 0;
-    loop {
-        let instr = parse_next_instr(&mut pc, &input_prog);
+    #[test]
+    fn day_9_programs() {
+        let boost = intcode::load_program("../09/input");
+        assert_eq!(run(boost, &[2]).unwrap().0, vec![76642]);
+
+        // Writes past the end of the program grow memory.
+        let (_, memory) = run(vec![1101, 2, 3, 7, 99], &[]).unwrap();
+        assert_eq!(memory, vec![1101, 2, 3, 7, 99, 0, 0, 5]);
+
+        assert_eq!(
+            run(vec![11101, 2, 3, 7, 99], &[]),
+            Err(HaltError::Fault(VmError::ImmediateWrite {
+                pc: 0,
+                instr: 11101
+            }))
+        );
+        assert_eq!(
+            run(vec![3, 0, 3, 0, 99], &[1]),
+            Err(HaltError::NeedsInput { pc: 2 })
+        );
+        assert_eq!(
+            run(vec![1105, 1, 0], &[]),
+            Err(HaltError::InfiniteLoop { pc: 0 })
+        );
+    }
 
-        match instr {
-            ParsedInstruction::Add { op1, op2, dest } => {
-                input_prog[dest] = op1 + op2;
-            }
-            ParsedInstruction::Multiply { op1, op2, dest } => {
-                input_prog[dest] = op1 * op2;
-            }
-            ParsedInstruction::Input { dest } => {
-                println!("Input {}", dest);
-                let stdin = io::stdin();
-                let line1 = stdin.lock().lines().next().unwrap().unwrap();
-                input_prog[dest] = line1.parse::<i32>().unwrap();
-            }
-            ParsedInstruction::Output { src } => {
-                println!("Output");
-                let out = input_prog[src];
-                println!("{}", out);
-            }
-            ParsedInstruction::JumpTrue { test, jump_dest } => {
-                if test != 0 {
-                    pc = jump_dest;
-                }
-            }
-            ParsedInstruction::JumpFalse { test, jump_dest } => {
-                if test == 0 {
-                    pc = jump_dest;
-                }
+    #[test]
+    fn diagnostics() {
+        let program = intcode::load_program("input");
+        let outputs = run_diagnostics(program.to_vec(), 1).unwrap();
+        assert_eq!(
+            diagnose(&outputs),
+            Diagnosis::Passed {
+                tests: 9,
+                code: 5346030
             }
-            ParsedInstruction::LessThan { op1, op2, dest } => {
-                input_prog[dest] = if op1 < op2 { 1 } else { 0 };
+        );
+        let outputs = run_diagnostics(program, 5).unwrap();
+        assert_eq!(
+            diagnose(&outputs),
+            Diagnosis::Passed {
+                tests: 0,
+                code: 513116
             }
+        );
 
-            ParsedInstruction::Equals { op1, op2, dest } => {
-                input_prog[dest] = if op1 == op2 { 1 } else { 0 };
+        assert_eq!(
+            diagnose(&[0, 3, 0, -1, 42]),
+            Diagnosis::Failed {
+                failures: vec![(1, 3), (3, -1)],
+                code: 42
             }
-
-            ParsedInstruction::Halt => {
-                println!("HALTING");
-                break;
-            }
-        }
-    }
-}
-
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        panic!("Provide one argument with path to the program");
+        );
+        assert_eq!(diagnose(&[]), Diagnosis::NoOutput);
     }
-
-    let file = std::fs::File::open(&args[1]).unwrap();
-    let mut reader = std::io::BufReader::new(file);
-    let mut file_string = String::new();
-    reader
-        .read_to_string(&mut file_string)
-        .expect("Unable to read file");
-
-    // load program
-    let no_whitespace_str: String = file_string
-        .split_whitespace()
-        .collect::<Vec<&str>>()
-        .join("");
-    let program: Vec<i32> = no_whitespace_str
-        .split(",")
-        .into_iter()
-        .map(|x| x.parse::<i32>().unwrap())
-        .collect();
-    process(program);
-}
-
-#[cfg(test)]
-mod tests {
-
-    #[test]
-    fn test1() {}
 }
@@ -1,252 +1,38 @@
+extern crate intcode;
 extern crate rustbox;
+use intcode::{Limits, LineInput, Machine, RunStatus, StdoutOutput};
 use rustbox::{InitOptions, RustBox};
-use std::cell::RefCell;
 use std::cmp::Ordering;
 use std::default::Default;
-use std::io::{BufRead, Read};
 use std::thread;
 use std::time;
 use std::time::Duration;
 
-#[derive(Copy, Clone, PartialEq)]
-enum Opcode {
-    Add = 1,
-    Multiply = 2,
-    Input = 3,
-    Output = 4,
-    JumpTrue = 5,
-    JumpFalse = 6,
-    LessThan = 7,
-    Equals = 8,
-    AdjustRelBase = 9,
-    Halt = 99,
-}
-
-impl Opcode {
-    fn from_i64(value: i64) -> Result<Opcode, String> {
-        match value {
-            1 => Ok(Opcode::Add),
-            2 => Ok(Opcode::Multiply),
-            3 => Ok(Opcode::Input),
-            4 => Ok(Opcode::Output),
-            5 => Ok(Opcode::JumpTrue),
-            6 => Ok(Opcode::JumpFalse),
-            7 => Ok(Opcode::LessThan),
-            8 => Ok(Opcode::Equals),
-            9 => Ok(Opcode::AdjustRelBase),
-            99 => Ok(Opcode::Halt),
-            _ => Err(format!("Invalid Opcode: {}", value)),
-        }
-    }
-}
-
-#[derive(Copy, Clone, PartialEq, Debug)]
-enum OperandMode {
-    Position = 0,
-    Immediate = 1,
-    Relative = 2,
-}
-
-impl OperandMode {
-    fn from_i64(value: i64) -> Result<OperandMode, String> {
-        match value {
-            0 => Ok(OperandMode::Position),
-            1 => Ok(OperandMode::Immediate),
-            2 => Ok(OperandMode::Relative),
-            _ => Err(format!("Invalid OperandMode: {}", value)),
-        }
-    }
-}
-
-fn operand_modes(x: i64) -> [OperandMode; 3] {
-    let mut res: [OperandMode; 3] = [OperandMode::Position; 3];
-
-    let mut x_div = x;
-    res[0] = OperandMode::from_i64(x_div % 10).expect("");
-    x_div = x_div / 10;
-    res[1] = OperandMode::from_i64(x_div % 10).expect("");
-    x_div = x_div / 10;
-    res[2] = OperandMode::from_i64(x_div % 10).expect("");
-
-    res
-}
-
-#[derive(Debug)]
-enum ParsedInstruction {
-    Add { op1: i64, op2: i64, dest: usize },
-    Multiply { op1: i64, op2: i64, dest: usize },
-    Input { dest: usize },
-    Output { out: i64 },
-    JumpTrue { test: i64, jump_dest: usize },
-    JumpFalse { test: i64, jump_dest: usize },
-    LessThan { op1: i64, op2: i64, dest: usize },
-    Equals { op1: i64, op2: i64, dest: usize },
-    AdjustRelBase { adj: i64 },
-    Halt,
-}
-
-fn get_ext(v: &mut Vec<i64>, index: usize) -> i64 {
-    if index >= v.len() {
-        v.resize(index + 1, 0);
-    }
-    *v.get(index).unwrap()
-}
-fn get_mut_ext(v: &mut Vec<i64>, index: usize) -> &mut i64 {
-    if index >= v.len() {
-        v.resize(index + 1, 0);
-    }
-    v.get_mut(index).unwrap()
-}
-
-fn load_operands<'a>(
-    op: Opcode,
-    op_modes: [OperandMode; 3],
-    ps: &mut ProcState,
-    prog: &mut Vec<i64>,
-) -> ParsedInstruction {
-    let mut parse_operand = |i, read| -> i64 {
-        let raw_opand_val = get_ext(prog, ps.prog_count as usize);
-        let op = if read {
-            match i {
-                OperandMode::Position => get_ext(prog, raw_opand_val as usize),
-                OperandMode::Immediate => raw_opand_val,
-                OperandMode::Relative => get_ext(prog, (ps.rel_base + raw_opand_val) as usize),
-            }
-        } else {
-            match i {
-                OperandMode::Position => raw_opand_val,
-                OperandMode::Immediate => raw_opand_val,
-                OperandMode::Relative => ps.rel_base + raw_opand_val,
-            }
-        };
-        ps.prog_count += 1;
-        op
-    };
-
-    match op {
-        Opcode::Add => ParsedInstruction::Add {
-            op1: parse_operand(op_modes[0], true),
-            op2: parse_operand(op_modes[1], true),
-            dest: parse_operand(op_modes[2], false) as usize,
-        },
-        Opcode::Multiply => ParsedInstruction::Multiply {
-            op1: parse_operand(op_modes[0], true),
-            op2: parse_operand(op_modes[1], true),
-            dest: parse_operand(op_modes[2], false) as usize,
-        },
-        Opcode::Input => ParsedInstruction::Input {
-            dest: parse_operand(op_modes[0], false) as usize,
-        },
-        Opcode::Output => ParsedInstruction::Output {
-            out: parse_operand(op_modes[0], true),
-        },
-        Opcode::JumpTrue => ParsedInstruction::JumpTrue {
-            test: parse_operand(op_modes[0], true),
-            jump_dest: parse_operand(op_modes[1], true) as usize,
-        },
-        Opcode::JumpFalse => ParsedInstruction::JumpFalse {
-            test: parse_operand(op_modes[0], true),
-            jump_dest: parse_operand(op_modes[1], true) as usize,
-        },
-        Opcode::LessThan => ParsedInstruction::LessThan {
-            op1: parse_operand(op_modes[0], true),
-            op2: parse_operand(op_modes[1], true),
-            dest: parse_operand(op_modes[2], false) as usize,
-        },
-        Opcode::Equals => ParsedInstruction::Equals {
-            op1: parse_operand(op_modes[0], true),
-            op2: parse_operand(op_modes[1], true),
-            dest: parse_operand(op_modes[2], false) as usize,
-        },
-        Opcode::AdjustRelBase => ParsedInstruction::AdjustRelBase {
-            adj: parse_operand(op_modes[0], true) as i64,
+fn manual_input(rb: &RustBox) -> i64 {
+    let now = time::Instant::now();
+    let timeout = Duration::from_millis(30);
+    let input = match rb.peek_event(timeout, false) {
+        Ok(rustbox::Event::KeyEvent(key)) => match key {
+            rustbox::Key::Left => -1,
+            rustbox::Key::Right => 1,
+            _ => 0,
         },
-        Opcode::Halt => ParsedInstruction::Halt,
+        Err(e) => panic!("{:?}", e),
+        _ => 0,
+    };
+    if now.elapsed() <= timeout {
+        thread::sleep(timeout - now.elapsed());
     }
+    input
 }
 
-fn parse_next_instr(ps: &mut ProcState, prog: &mut Vec<i64>) -> ParsedInstruction {
-    let combined_opcode = get_ext(prog, ps.prog_count);
-    ps.prog_count += 1;
-    let opcode_int = combined_opcode % 100;
-    let operand_modes_int = combined_opcode / 100;
-    let opcode = Opcode::from_i64(opcode_int).unwrap();
-    let operand_modes = operand_modes(operand_modes_int);
-
-    load_operands(opcode, operand_modes, ps, prog)
-}
-
-#[derive(Copy, Clone, PartialEq, Debug)]
-struct ProcState {
-    prog_count: usize,
-    rel_base: i64,
-    halted: bool,
-}
-
-fn process<I, O>(prog: &mut Vec<i64>, mut pc: ProcState, input: &mut I, output: &mut O) -> ProcState
-where
-    I: FnMut() -> Option<String>,
-    O: FnMut(i64) -> (),
-{
-    loop {
-        let cached_pc = pc;
-        let instr = parse_next_instr(&mut pc, prog);
-        // eprintln!("{:?}: {:?}", cached_pc, instr);
-
-        match instr {
-            ParsedInstruction::Add { op1, op2, dest } => {
-                *get_mut_ext(prog, dest) = op1 + op2;
-            }
-            ParsedInstruction::Multiply { op1, op2, dest } => {
-                *get_mut_ext(prog, dest) = op1 * op2;
-            }
-            ParsedInstruction::Input { dest } => match input() {
-                Some(line) => {
-                    //eprintln!("Input {} -> {}", line, dest);
-                    *get_mut_ext(prog, dest) = line.parse::<i64>().unwrap();
-                }
-                None => {
-                    eprintln!("Breaking to wait for input");
-                    pc = cached_pc;
-                    break;
-                }
-            },
-            ParsedInstruction::Output { out } => {
-                output(out);
-            }
-            ParsedInstruction::JumpTrue { test, jump_dest } => {
-                if test != 0 {
-                    pc.prog_count = jump_dest;
-                }
-            }
-            ParsedInstruction::JumpFalse { test, jump_dest } => {
-                if test == 0 {
-                    pc.prog_count = jump_dest;
-                }
-            }
-            ParsedInstruction::LessThan { op1, op2, dest } => {
-                *get_mut_ext(prog, dest) = if op1 < op2 { 1 } else { 0 };
-            }
-
-            ParsedInstruction::Equals { op1, op2, dest } => {
-                *get_mut_ext(prog, dest) = if op1 == op2 { 1 } else { 0 };
-            }
-
-            ParsedInstruction::AdjustRelBase { adj } => {
-                pc.rel_base += adj;
-            }
-
-            ParsedInstruction::Halt => {
-                eprintln!("\nHALTING, press 'q' to exit");
-                pc.halted = true;
-                break;
-            }
-        }
-    }
-
-    pc
-}
-fn run_game(mut prog: Vec<i64>) {
+fn run_game(
+    prog: Vec<i64>,
+    manual: bool,
+    trace_path: Option<&str>,
+    profile_path: Option<&str>,
+    limits: Limits,
+) {
     struct ProgState {
         tile_x: i32,
         tile_y: i32,
@@ -255,7 +41,7 @@ fn run_game(mut prog: Vec<i64>) {
         read_state: i32,
         rb: RustBox,
     };
-    let prog_state_ref: RefCell<ProgState> = RefCell::new(ProgState {
+    let mut ps = ProgState {
         tile_x: 0,
         tile_y: 0,
         ball_pos: (0, 0),
@@ -266,47 +52,54 @@ fn run_game(mut prog: Vec<i64>) {
             ..Default::default()
         })
         .unwrap(),
-    });
-
-    let mut buffer_input = || {
-        let ps_bow = prog_state_ref.borrow();
-        let input = match ps_bow.paddle_pos.0.cmp(&ps_bow.ball_pos.0) {
-            Ordering::Less => 1,
-            Ordering::Equal => 0,
-            Ordering::Greater => -1,
-        };
-        Some(input.to_string())
     };
-    let mut manual_input = || {
-        let now = time::Instant::now();
-        let timeout = Duration::from_millis(30);
-        let input = match prog_state_ref.borrow_mut().rb.peek_event(timeout, false) {
-            Ok(rustbox::Event::KeyEvent(key)) => match key {
-                rustbox::Key::Left => -1,
-                rustbox::Key::Right => 1,
-                _ => 0,
-            },
-            Err(e) => panic!("{:?}", e),
-            _ => 0,
+
+    let mut machine = Machine::new(prog);
+    machine.set_loop_detection(true);
+    limits.apply(&mut machine);
+    if trace_path.is_some() {
+        machine.start_trace();
+    }
+    if profile_path.is_some() {
+        machine.start_profile();
+    }
+    loop {
+        let x = match machine.run_until_output() {
+            Ok(RunStatus::Output(x)) => x,
+            Ok(RunStatus::NeedsInput) => {
+                let input = if manual {
+                    manual_input(&ps.rb)
+                } else {
+                    match ps.paddle_pos.0.cmp(&ps.ball_pos.0) {
+                        Ordering::Less => 1,
+                        Ordering::Equal => 0,
+                        Ordering::Greater => -1,
+                    }
+                };
+                machine.push_input(input);
+                continue;
+            }
+            Ok(RunStatus::Halted) => break,
+            Ok(status) => {
+                eprintln!("Game stopped: {:?}", status);
+                break;
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
         };
-        if now.elapsed() <= timeout {
-            thread::sleep(timeout - now.elapsed());
-        }
-        Some(input.to_string())
-    };
-    let mut buffer_output = |x: i64| {
-        let mut ps_bow = prog_state_ref.borrow_mut();
-        match ps_bow.read_state {
+        match ps.read_state {
             0 => {
-                ps_bow.tile_x = x as i32;
+                ps.tile_x = x as i32;
             }
             1 => {
-                ps_bow.tile_y = x as i32;
+                ps.tile_y = x as i32;
             }
             2 => {
-                if ps_bow.tile_x == -1 && ps_bow.tile_y == 0 {
+                if ps.tile_x == -1 && ps.tile_y == 0 {
                     let score = x;
-                    ps_bow.rb.print(
+                    ps.rb.print(
                         0 as usize,
                         22 as usize,
                         rustbox::RB_NORMAL,
@@ -314,7 +107,7 @@ fn run_game(mut prog: Vec<i64>) {
                         rustbox::Color::Black,
                         &("Score: ".to_string() + &score.to_string()),
                     );
-                    ps_bow.rb.present();
+                    ps.rb.present();
 
                 } else {
                     let tile_id = x;
@@ -323,42 +116,45 @@ fn run_game(mut prog: Vec<i64>) {
                         1 => '+',
                         2 => '□',
                         3 => {
-                            ps_bow.paddle_pos = (ps_bow.tile_x, ps_bow.tile_y);
+                            ps.paddle_pos = (ps.tile_x, ps.tile_y);
                             '='
                         }
                         4 => {
-                            ps_bow.ball_pos = (ps_bow.tile_x, ps_bow.tile_y);
+                            ps.ball_pos = (ps.tile_x, ps.tile_y);
                             '●'
                         }
                         _ => ' ',
                     };
 
-                    ps_bow.rb.print_char(
-                        ps_bow.tile_x as usize,
-                        ps_bow.tile_y as usize,
+                    ps.rb.print_char(
+                        ps.tile_x as usize,
+                        ps.tile_y as usize,
                         rustbox::RB_NORMAL,
                         rustbox::Color::White,
                         rustbox::Color::Black,
                         tile_char,
                     );
 
-                    ps_bow.rb.present();
+                    ps.rb.present();
                 }
             }
             _ => panic!("ASDF"),
         }
-        ps_bow.read_state = (ps_bow.read_state + 1) % 3;
-    };
-    let mut ps = ProcState {
-        prog_count: 0,
-        rel_base: 0,
-        halted: false,
-    };
-    while !ps.halted {
-        ps = process(&mut prog, ps, &mut buffer_input, &mut buffer_output);
+        ps.read_state = (ps.read_state + 1) % 3;
     }
+    if let (Some(path), Some(trace)) = (trace_path, machine.take_trace()) {
+        if let Err(e) = trace.save(path) {
+            eprintln!("{}", e);
+        }
+    }
+    if let (Some(path), Some(profile)) = (profile_path, machine.take_profile()) {
+        if let Err(e) = std::fs::write(path, profile.report(machine.memory(), 20)) {
+            eprintln!("{}: {}", path, e);
+        }
+    }
+    eprintln!("\nHALTING, press 'q' to exit");
     loop {
-        match prog_state_ref.borrow_mut().rb.poll_event(false) {
+        match ps.rb.poll_event(false) {
             Ok(rustbox::Event::KeyEvent(key)) => match key {
                 rustbox::Key::Char('q') => {
                     break;
@@ -371,55 +167,38 @@ fn run_game(mut prog: Vec<i64>) {
     }
 }
 
-fn run_with_stdin_stdout(mut prog: Vec<i64>) {
-    let mut buffer_input = || {
-        let out = {
-            let stdin = std::io::stdin();
-            let handle = stdin.lock();
-            let res = handle.lines().next().unwrap();
-            res
-        };
-        match out {
-            Ok(x) => Some(x),
-            Err(_) => None,
-        }
-    };
-    let mut buffer_output = |x: i64| {
-        println!("OUT-> {}", x);
-    };
-    let ps = ProcState {
-        prog_count: 0,
-        rel_base: 0,
-        halted: false,
-    };
-    process(&mut prog, ps, &mut buffer_input, &mut buffer_output);
+fn run_with_stdin_stdout(prog: Vec<i64>) {
+    let mut machine = Machine::new(prog);
+    if let Err(e) = machine.run(&mut LineInput::stdin(), &mut StdoutOutput) {
+        eprintln!("{}", e);
+    }
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        panic!("Provide one argument with path to the program");
+    let mut args: Vec<String> = std::env::args().collect();
+    let patches = intcode::take_patch_args(&mut args).unwrap_or_else(|e| panic!("{}", e));
+    let limits = intcode::take_limit_args(&mut args).unwrap_or_else(|e| panic!("{}", e));
+    let usage = "Provide one argument with path to the program, optionally followed by \
+                 --trace <file>, --profile <file>, --budget <instructions>, --timeout <seconds>, \
+                 --patch <addr=value,...> and/or --patch-file <file>";
+    if args.len() % 2 != 0 {
+        panic!("{}", usage);
+    }
+    let mut trace_path = None;
+    let mut profile_path = None;
+    for opt in args[2..].chunks(2) {
+        match opt[0].as_str() {
+            "--trace" => trace_path = Some(opt[1].as_str()),
+            "--profile" => profile_path = Some(opt[1].as_str()),
+            _ => panic!("{}", usage),
+        }
     }
 
-    let file = std::fs::File::open(&args[1]).unwrap();
-    let mut reader = std::io::BufReader::new(file);
-    let mut file_string = String::new();
-    reader
-        .read_to_string(&mut file_string)
-        .expect("Unable to read file");
-
-    // load program
-    let no_whitespace_str: String = file_string
-        .split_whitespace()
-        .collect::<Vec<&str>>()
-        .join("");
-    let program: Vec<i64> = no_whitespace_str
-        .split(",")
-        .into_iter()
-        .map(|x| x.parse::<i64>().unwrap())
-        .collect();
+    let mut program = intcode::load_program(&args[1]);
+    intcode::apply_patches(&mut program, &patches).unwrap_or_else(|e| panic!("{}", e));
 
-    run_game(program.to_vec());
+    run_game(program.to_vec(), false, trace_path, profile_path, limits);
+    eprintln!("{}", intcode::describe_patches(&patches));
 }
 
 #[cfg(test)]
@@ -1,255 +1,12 @@
-use std::cell::RefCell;
-use std::char;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::fmt;
-use std::io::{stdin, stdout, BufRead, Read, Write};
+extern crate intcode;
 extern crate nalgebra as na;
+use intcode::{AsciiMachine, LineInput, Machine, RunStatus, StdoutOutput};
 type Vec2i = na::Vector2<i32>;
 
-#[derive(Copy, Clone, PartialEq)]
-enum Opcode {
-    Add = 1,
-    Multiply = 2,
-    Input = 3,
-    Output = 4,
-    JumpTrue = 5,
-    JumpFalse = 6,
-    LessThan = 7,
-    Equals = 8,
-    AdjustRelBase = 9,
-    Halt = 99,
-}
-
-impl Opcode {
-    fn from_i64(value: i64) -> Result<Opcode, String> {
-        match value {
-            1 => Ok(Opcode::Add),
-            2 => Ok(Opcode::Multiply),
-            3 => Ok(Opcode::Input),
-            4 => Ok(Opcode::Output),
-            5 => Ok(Opcode::JumpTrue),
-            6 => Ok(Opcode::JumpFalse),
-            7 => Ok(Opcode::LessThan),
-            8 => Ok(Opcode::Equals),
-            9 => Ok(Opcode::AdjustRelBase),
-            99 => Ok(Opcode::Halt),
-            _ => Err(format!("Invalid Opcode: {}", value)),
-        }
-    }
-}
-
-#[derive(Copy, Clone, PartialEq, Debug)]
-enum OperandMode {
-    Position = 0,
-    Immediate = 1,
-    Relative = 2,
-}
-
-impl OperandMode {
-    fn from_i64(value: i64) -> Result<OperandMode, String> {
-        match value {
-            0 => Ok(OperandMode::Position),
-            1 => Ok(OperandMode::Immediate),
-            2 => Ok(OperandMode::Relative),
-            _ => Err(format!("Invalid OperandMode: {}", value)),
-        }
-    }
-}
-
-fn operand_modes(x: i64) -> [OperandMode; 3] {
-    let mut res: [OperandMode; 3] = [OperandMode::Position; 3];
-
-    let mut x_div = x;
-    res[0] = OperandMode::from_i64(x_div % 10).expect("");
-    x_div = x_div / 10;
-    res[1] = OperandMode::from_i64(x_div % 10).expect("");
-    x_div = x_div / 10;
-    res[2] = OperandMode::from_i64(x_div % 10).expect("");
-
-    res
-}
-
-#[derive(Debug)]
-enum ParsedInstruction {
-    Add { op1: i64, op2: i64, dest: usize },
-    Multiply { op1: i64, op2: i64, dest: usize },
-    Input { dest: usize },
-    Output { out: i64 },
-    JumpTrue { test: i64, jump_dest: usize },
-    JumpFalse { test: i64, jump_dest: usize },
-    LessThan { op1: i64, op2: i64, dest: usize },
-    Equals { op1: i64, op2: i64, dest: usize },
-    AdjustRelBase { adj: i64 },
-    Halt,
-}
-
-fn get_ext(v: &mut Vec<i64>, index: usize) -> i64 {
-    if index >= v.len() {
-        v.resize(index + 1, 0);
-    }
-    *v.get(index).unwrap()
-}
-fn get_mut_ext(v: &mut Vec<i64>, index: usize) -> &mut i64 {
-    if index >= v.len() {
-        v.resize(index + 1, 0);
-    }
-    v.get_mut(index).unwrap()
-}
-
-fn load_operands<'a>(
-    op: Opcode,
-    op_modes: [OperandMode; 3],
-    ps: &mut ProcState,
-    prog: &mut Vec<i64>,
-) -> ParsedInstruction {
-    let mut parse_operand = |i, read| -> i64 {
-        let raw_opand_val = get_ext(prog, ps.prog_count as usize);
-        let op = if read {
-            match i {
-                OperandMode::Position => get_ext(prog, raw_opand_val as usize),
-                OperandMode::Immediate => raw_opand_val,
-                OperandMode::Relative => get_ext(prog, (ps.rel_base + raw_opand_val) as usize),
-            }
-        } else {
-            match i {
-                OperandMode::Position => raw_opand_val,
-                OperandMode::Immediate => raw_opand_val,
-                OperandMode::Relative => ps.rel_base + raw_opand_val,
-            }
-        };
-        ps.prog_count += 1;
-        op
-    };
-
-    match op {
-        Opcode::Add => ParsedInstruction::Add {
-            op1: parse_operand(op_modes[0], true),
-            op2: parse_operand(op_modes[1], true),
-            dest: parse_operand(op_modes[2], false) as usize,
-        },
-        Opcode::Multiply => ParsedInstruction::Multiply {
-            op1: parse_operand(op_modes[0], true),
-            op2: parse_operand(op_modes[1], true),
-            dest: parse_operand(op_modes[2], false) as usize,
-        },
-        Opcode::Input => ParsedInstruction::Input {
-            dest: parse_operand(op_modes[0], false) as usize,
-        },
-        Opcode::Output => ParsedInstruction::Output {
-            out: parse_operand(op_modes[0], true),
-        },
-        Opcode::JumpTrue => ParsedInstruction::JumpTrue {
-            test: parse_operand(op_modes[0], true),
-            jump_dest: parse_operand(op_modes[1], true) as usize,
-        },
-        Opcode::JumpFalse => ParsedInstruction::JumpFalse {
-            test: parse_operand(op_modes[0], true),
-            jump_dest: parse_operand(op_modes[1], true) as usize,
-        },
-        Opcode::LessThan => ParsedInstruction::LessThan {
-            op1: parse_operand(op_modes[0], true),
-            op2: parse_operand(op_modes[1], true),
-            dest: parse_operand(op_modes[2], false) as usize,
-        },
-        Opcode::Equals => ParsedInstruction::Equals {
-            op1: parse_operand(op_modes[0], true),
-            op2: parse_operand(op_modes[1], true),
-            dest: parse_operand(op_modes[2], false) as usize,
-        },
-        Opcode::AdjustRelBase => ParsedInstruction::AdjustRelBase {
-            adj: parse_operand(op_modes[0], true) as i64,
-        },
-        Opcode::Halt => ParsedInstruction::Halt,
-    }
-}
-
-fn parse_next_instr(ps: &mut ProcState, prog: &mut Vec<i64>) -> ParsedInstruction {
-    let combined_opcode = get_ext(prog, ps.prog_count);
-    ps.prog_count += 1;
-    let opcode_int = combined_opcode % 100;
-    let operand_modes_int = combined_opcode / 100;
-    let opcode = Opcode::from_i64(opcode_int).unwrap();
-    let operand_modes = operand_modes(operand_modes_int);
-
-    load_operands(opcode, operand_modes, ps, prog)
-}
-
-#[derive(Copy, Clone, PartialEq, Debug)]
-struct ProcState {
-    prog_count: usize,
-    rel_base: i64,
-    halted: bool,
-}
-
-fn process<I, O>(prog: &mut Vec<i64>, mut pc: ProcState, input: &mut I, output: &mut O) -> ProcState
-where
-    I: FnMut() -> Option<String>,
-    O: FnMut(i64) -> (),
-{
-    loop {
-        let cached_pc = pc;
-        let instr = parse_next_instr(&mut pc, prog);
-        // eprintln!("{:?}: {:?}", cached_pc, instr);
-
-        match instr {
-            ParsedInstruction::Add { op1, op2, dest } => {
-                *get_mut_ext(prog, dest) = op1 + op2;
-            }
-            ParsedInstruction::Multiply { op1, op2, dest } => {
-                *get_mut_ext(prog, dest) = op1 * op2;
-            }
-            ParsedInstruction::Input { dest } => match input() {
-                Some(line) => {
-                    //eprintln!("Input {} -> {}", line, dest);
-                    let res = line.parse::<i64>().unwrap();
-                    *get_mut_ext(prog, dest) = res;
-                    if res == 0 {
-                        pc.halted = true;
-                    }
-                }
-                None => {
-                    eprintln!("Breaking to wait for input");
-                    pc = cached_pc;
-                    break;
-                }
-            },
-            ParsedInstruction::Output { out } => {
-                output(out);
-            }
-            ParsedInstruction::JumpTrue { test, jump_dest } => {
-                if test != 0 {
-                    pc.prog_count = jump_dest;
-                }
-            }
-            ParsedInstruction::JumpFalse { test, jump_dest } => {
-                if test == 0 {
-                    pc.prog_count = jump_dest;
-                }
-            }
-            ParsedInstruction::LessThan { op1, op2, dest } => {
-                *get_mut_ext(prog, dest) = if op1 < op2 { 1 } else { 0 };
-            }
-
-            ParsedInstruction::Equals { op1, op2, dest } => {
-                *get_mut_ext(prog, dest) = if op1 == op2 { 1 } else { 0 };
-            }
-
-            ParsedInstruction::AdjustRelBase { adj } => {
-                pc.rel_base += adj;
-            }
-
-            ParsedInstruction::Halt => {
-                eprintln!("\nHALTING, press 'q' to exit");
-                pc.halted = true;
-                break;
-            }
-        }
-    }
-
-    pc
-}
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 enum Tile {
@@ -285,102 +42,53 @@ impl Tile {
     }
 }
 
-fn run_game(mut prog: Vec<i64>) -> HashMap<Vec2i, Tile> {
-    struct ProgState {
-        cur_readout: Vec2i,
-        map: HashMap<Vec2i, Tile>,
-    };
-    let prog_state_ref: RefCell<ProgState> = RefCell::new(ProgState {
-        cur_readout: Vec2i::new(0, 0),
-        map: HashMap::new(),
-    });
-
-    let mut input = || {
-        // let mut p = prog_state_ref.borrow_mut();
-
-        Some("0".to_string())
-    };
+fn run_game(prog: Vec<i64>) -> HashMap<Vec2i, Tile> {
+    let mut map: HashMap<Vec2i, Tile> = HashMap::new();
 
-    //out
-    let mut output = |x: i64| {
-        let mut p = prog_state_ref.borrow_mut();
-        let tile = Tile::from_i64(x).unwrap();
-        if tile == Tile::NewLine {
-            p.cur_readout.x = 0;
-            p.cur_readout.y += 1;
-        } else {
-            let readout = p.cur_readout;
-            p.map.insert(readout, tile);
-            p.cur_readout.x += 1;
+    let mut camera = AsciiMachine::new(Machine::new(prog));
+    match camera.run() {
+        Ok(RunStatus::Halted) => (),
+        Ok(status) => eprintln!("Camera stopped: {:?}", status),
+        Err(e) => eprintln!("{}", e),
+    }
+    for (y, line) in camera.output_mut().take_lines().iter().enumerate() {
+        for (x, c) in line.chars().enumerate() {
+            match Tile::from_i64(c as i64) {
+                Ok(tile) => {
+                    map.insert(Vec2i::new(x as i32, y as i32), tile);
+                }
+                Err(e) => eprintln!("{} at {},{}", e, x, y),
+            }
         }
-    };
-    let mut ps = ProcState {
-        prog_count: 0,
-        rel_base: 0,
-        halted: false,
-    };
-    while !ps.halted {
-        ps = process(&mut prog, ps, &mut input, &mut output);
     }
 
-    let pb = prog_state_ref.borrow();
-    pb.map.clone()
+    map
 }
 
-fn run_game_b(mut prog: Vec<i64>, commands: Vec<i64>) {
-    struct ProgState {
-        cur_readout: Vec2i,
-        map: HashMap<Vec2i, Tile>,
-    };
-    let prog_state_ref: RefCell<ProgState> = RefCell::new(ProgState {
-        cur_readout: Vec2i::new(0, 0),
-        map: HashMap::new(),
-    });
+fn run_game_b(prog: Vec<i64>, routines: &[Vec<String>]) {
+    let mut robot = AsciiMachine::new(Machine::new(prog));
+    for routine in routines {
+        robot.send_line(&routine.join(",")).unwrap();
+    }
+    robot.send_line("n").unwrap();
 
-    let mut input_i = commands.iter();
-    let mut input = || {
-        // let mut p = prog_state_ref.borrow_mut();
-
-        Some(input_i.next().unwrap().to_string())
-    };
-
-    //out
-    let mut buffer_output = |x: i64| {
-        print!("{}", char::from_u32(x as u32).unwrap_or(' '));
-        eprint!("{}", x);
-    };
-    let mut ps = ProcState {
-        prog_count: 0,
-        rel_base: 0,
-        halted: false,
-    };
-    while !ps.halted {
-        ps = process(&mut prog, ps, &mut input, &mut buffer_output);
+    let status = robot.run();
+    for line in robot.output().lines() {
+        println!("{}", line);
+    }
+    match status {
+        Ok(RunStatus::Halted) => (),
+        Ok(status) => eprintln!("Robot stopped: {:?}", status),
+        Err(e) => eprintln!("{}", e),
     }
+    println!("Non-ASCII results: {:?}", robot.output().non_ascii());
 }
 
-fn run_with_stdin_stdout(mut prog: Vec<i64>) {
-    let mut buffer_input = || {
-        let out = {
-            let stdin = std::io::stdin();
-            let handle = stdin.lock();
-            let res = handle.lines().next().unwrap();
-            res
-        };
-        match out {
-            Ok(x) => Some(x),
-            Err(_) => None,
-        }
-    };
-    let mut buffer_output = |x: i64| {
-        println!("OUT-> {}", x);
-    };
-    let ps = ProcState {
-        prog_count: 0,
-        rel_base: 0,
-        halted: false,
-    };
-    process(&mut prog, ps, &mut buffer_input, &mut buffer_output);
+fn run_with_stdin_stdout(prog: Vec<i64>) {
+    let mut machine = Machine::new(prog);
+    if let Err(e) = machine.run(&mut LineInput::stdin(), &mut StdoutOutput) {
+        eprintln!("{}", e);
+    }
 }
 
 fn canonical_edge(a: &Vec2i, b: &Vec2i) -> (Vec2i, Vec2i) {
@@ -767,28 +475,20 @@ fn greedy_compression(input: &Vec<String>) -> (Vec<String>, Vec<String>, Vec<Str
 }
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let mut patches = intcode::take_patch_args(&mut args).unwrap_or_else(|e| panic!("{}", e));
     if args.len() != 2 {
-        panic!("Provide one argument with path to the program");
+        panic!(
+            "Provide one argument with path to the program, optionally followed by \
+             --patch <addr=value,...> and/or --patch-file <file> for part 2 (default 0=2)"
+        );
+    }
+    if patches.is_empty() {
+        // Wakes the robot up.
+        patches.push(intcode::Patch { addr: 0, value: 2 });
     }
 
-    let file = std::fs::File::open(&args[1]).unwrap();
-    let mut reader = std::io::BufReader::new(file);
-    let mut file_string = String::new();
-    reader
-        .read_to_string(&mut file_string)
-        .expect("Unable to read file");
-
-    // load program
-    let no_whitespace_str: String = file_string
-        .split_whitespace()
-        .collect::<Vec<&str>>()
-        .join("");
-    let program: Vec<i64> = no_whitespace_str
-        .split(",")
-        .into_iter()
-        .map(|x| x.parse::<i64>().unwrap())
-        .collect();
+    let program = intcode::load_program(&args[1]);
 
     let map = run_game(program.to_vec());
 
@@ -823,23 +523,10 @@ fn main() {
     }
     let (a, b, c, comp) = greedy_compression(&min_vec);
     let mut new_program = program.to_vec();
-    new_program[0] = 2;
+    intcode::apply_patches(&mut new_program, &patches).unwrap_or_else(|e| panic!("{}", e));
+    println!("{}", intcode::describe_patches(&patches));
 
-    let mut prog_input: Vec<i64> = Vec::new();
-    for abc in [comp, a, b, c].iter() {
-        for aa in abc {
-            for ch in aa.chars() {
-                prog_input.push(ch as i64);
-            }
-            prog_input.push(',' as i64);
-        }
-        prog_input.pop();
-        prog_input.push('\n' as i64);
-    }
-    prog_input.push('n' as i64);
-    prog_input.push('\n' as i64);
-    prog_input.push('\n' as i64);
-    run_game_b(new_program, prog_input);
+    run_game_b(new_program, &[comp, a, b, c]);
 }
 
 #[cfg(test)]
@@ -0,0 +1,78 @@
+/// How Add and Multiply deal with results that don't fit in an i64.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum Arithmetic {
+    /// Fault with `VmError::ArithmeticOverflow`.
+    #[default]
+    Checked,
+    /// Two's complement wrap around, what a release build did before.
+    Wrapping,
+    /// Clamp to `i64::MIN`/`i64::MAX`.
+    Saturating,
+    /// Compute the exact result in i128 and fault only if it can't be
+    /// stored. Same results as `Checked`, useful to cross-check it.
+    Widened,
+}
+
+impl Arithmetic {
+    /// Returns `None` on overflow for the faulting modes.
+    pub fn add(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Arithmetic::Checked => a.checked_add(b),
+            Arithmetic::Wrapping => Some(a.wrapping_add(b)),
+            Arithmetic::Saturating => Some(a.saturating_add(b)),
+            Arithmetic::Widened => narrow(i128::from(a) + i128::from(b)),
+        }
+    }
+
+    pub fn mul(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Arithmetic::Checked => a.checked_mul(b),
+            Arithmetic::Wrapping => Some(a.wrapping_mul(b)),
+            Arithmetic::Saturating => Some(a.saturating_mul(b)),
+            Arithmetic::Widened => narrow(i128::from(a) * i128::from(b)),
+        }
+    }
+}
+
+fn narrow(x: i128) -> Option<i64> {
+    if x < i128::from(i64::MIN) || x > i128::from(i64::MAX) {
+        None
+    } else {
+        Some(x as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Arithmetic; 4] = [
+        Arithmetic::Checked,
+        Arithmetic::Wrapping,
+        Arithmetic::Saturating,
+        Arithmetic::Widened,
+    ];
+
+    #[test]
+    fn in_range_results_agree() {
+        for &mode in &ALL {
+            assert_eq!(mode.mul(34915192, 34915192), Some(1219070632396864));
+            assert_eq!(mode.add(i64::MAX, i64::MIN), Some(-1));
+        }
+    }
+
+    #[test]
+    fn overflow() {
+        let big = 1219070632396864;
+        assert_eq!(Arithmetic::Checked.mul(big, big), None);
+        assert_eq!(Arithmetic::Widened.mul(big, big), None);
+        assert_eq!(
+            Arithmetic::Wrapping.mul(big, big),
+            Some(big.wrapping_mul(big))
+        );
+        assert_eq!(Arithmetic::Saturating.mul(big, -big), Some(i64::MIN));
+        assert_eq!(Arithmetic::Checked.add(i64::MAX, 1), None);
+        assert_eq!(Arithmetic::Wrapping.add(i64::MAX, 1), Some(i64::MIN));
+        assert_eq!(Arithmetic::Saturating.add(i64::MAX, 1), Some(i64::MAX));
+    }
+}
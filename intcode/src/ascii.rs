@@ -0,0 +1,230 @@
+//! Talking to programs that read and write ASCII text, one character per
+//! value, like the day 17 vacuum robot.
+
+use error::VmError;
+use io::Output;
+use machine::{Machine, RunStatus};
+use std::io::{self, BufRead, Write};
+
+/// Collects output values into lines of text. Values outside the ASCII
+/// range, such as a final numeric answer, are kept apart as non-ASCII
+/// results.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct AsciiOutput {
+    lines: Vec<String>,
+    partial: String,
+    non_ascii: Vec<i64>,
+}
+
+impl AsciiOutput {
+    pub fn new() -> AsciiOutput {
+        AsciiOutput::default()
+    }
+
+    /// Complete lines received so far and not yet taken.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub fn take_lines(&mut self) -> Vec<String> {
+        self.lines.split_off(0)
+    }
+
+    /// Text after the last newline, typically a prompt.
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+
+    pub fn take_partial(&mut self) -> String {
+        self.partial.split_off(0)
+    }
+
+    pub fn non_ascii(&self) -> &[i64] {
+        &self.non_ascii
+    }
+}
+
+impl Output for AsciiOutput {
+    fn write(&mut self, value: i64) {
+        match value {
+            10 => self.lines.push(self.partial.split_off(0)),
+            0..=127 => self.partial.push(value as u8 as char),
+            _ => self.non_ascii.push(value),
+        }
+    }
+}
+
+/// The values that send `text` as input, or the first character that
+/// isn't ASCII.
+pub fn ascii_values(text: &str) -> Result<Vec<i64>, String> {
+    text.chars()
+        .map(|c| {
+            if c.is_ascii() {
+                Ok(c as i64)
+            } else {
+                Err(format!("Not ASCII: {:?}", c))
+            }
+        })
+        .collect()
+}
+
+/// A machine that is fed strings and whose output is read as lines.
+#[derive(Clone, Debug)]
+pub struct AsciiMachine {
+    machine: Machine,
+    output: AsciiOutput,
+}
+
+impl AsciiMachine {
+    pub fn new(machine: Machine) -> AsciiMachine {
+        AsciiMachine {
+            machine,
+            output: AsciiOutput::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    pub fn output(&self) -> &AsciiOutput {
+        &self.output
+    }
+
+    pub fn output_mut(&mut self) -> &mut AsciiOutput {
+        &mut self.output
+    }
+
+    /// Queues `text` as input. Nothing is queued if any of it isn't ASCII.
+    pub fn send(&mut self, text: &str) -> Result<(), String> {
+        for value in ascii_values(text)? {
+            self.machine.push_input(value);
+        }
+        Ok(())
+    }
+
+    /// Queues `line` followed by a newline.
+    pub fn send_line(&mut self, line: &str) -> Result<(), String> {
+        self.send(line)?;
+        self.machine.push_input(10);
+        Ok(())
+    }
+
+    /// Runs until the program halts or wants more input than was sent.
+    pub fn run(&mut self) -> Result<RunStatus, VmError> {
+        self.machine.run_until_input(&mut self.output)
+    }
+
+    /// Connects the program to a terminal: its text goes to `out` as it
+    /// arrives and each time it runs out of input another line is read
+    /// from `input`. Returns `NeedsInput` once `input` is exhausted.
+    pub fn interactive<R, W>(&mut self, mut input: R, mut out: W) -> Result<RunStatus, VmError>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        loop {
+            let status = self.run()?;
+            for line in self.output.take_lines() {
+                let _ = writeln!(out, "{}", line);
+            }
+            let _ = write!(out, "{}", self.output.take_partial());
+            for value in self.output.non_ascii.drain(..) {
+                let _ = writeln!(out, "Non-ASCII result: {}", value);
+            }
+            let _ = out.flush();
+            if status != RunStatus::NeedsInput {
+                return Ok(status);
+            }
+
+            let mut line = String::new();
+            match input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Ok(status),
+                Ok(_) => (),
+            }
+            if let Err(e) = self.send_line(line.trim_end_matches(&['\r', '\n'][..])) {
+                let _ = writeln!(out, "{}", e);
+            }
+        }
+    }
+
+    /// `interactive` on stdin and stdout.
+    pub fn interactive_stdio(&mut self) -> Result<RunStatus, VmError> {
+        self.interactive(io::stdin().lock(), io::stdout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asm::assemble;
+
+    // Echoes each line back upper-cased until it reads an empty line, then
+    // outputs how many lines it saw.
+    const SHOUT: &str = "
+    prompt: out #'>'
+            out #' '
+    next:   in [c]
+            eq [c], #10, [t]
+            jt [t], #eol
+            lt [c], #'a', [t]
+            jt [t], #echo
+            add [c], #-32, [c]
+    echo:   out [c]
+            add #1, #0, [len]
+            jt #1, #next
+    eol:    jf [len], #done
+            out #10
+            add [lines], #1, [lines]
+            add #0, #0, [len]
+            jt #1, #prompt
+    done:   out [lines]
+            out #1000
+            hlt
+    c:      data 0
+    t:      data 0
+    len:    data 0
+    lines:  data 0
+    ";
+
+    #[test]
+    fn lines_and_non_ascii() {
+        let mut machine = AsciiMachine::new(Machine::new(assemble(SHOUT).unwrap()));
+        machine.send_line("hello").unwrap();
+        machine.send("wor").unwrap();
+        assert_eq!(machine.run(), Ok(RunStatus::NeedsInput));
+        assert_eq!(machine.output().lines(), &["> HELLO".to_string()][..]);
+        assert_eq!(machine.output().partial(), "> WOR");
+
+        assert!(machine.send("ä").is_err());
+        machine.send_line("ld").unwrap();
+        machine.send_line("").unwrap();
+        assert_eq!(machine.run(), Ok(RunStatus::Halted));
+        assert_eq!(
+            machine.output_mut().take_lines(),
+            vec!["> HELLO", "> WORLD"]
+        );
+        assert_eq!(machine.output().partial(), "> \u{2}");
+        assert_eq!(machine.output().non_ascii(), &[1000][..]);
+    }
+
+    #[test]
+    fn interactive_session() {
+        let mut machine = AsciiMachine::new(Machine::new(assemble(SHOUT).unwrap()));
+        let mut out = Vec::new();
+        let status = machine.interactive("abc\n\n".as_bytes(), &mut out);
+        assert_eq!(status, Ok(RunStatus::Halted));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "> ABC\n> \u{1}Non-ASCII result: 1000\n"
+        );
+
+        let mut machine = AsciiMachine::new(Machine::new(assemble(SHOUT).unwrap()));
+        let status = machine.interactive("abc".as_bytes(), &mut Vec::new());
+        assert_eq!(status, Ok(RunStatus::NeedsInput));
+    }
+}
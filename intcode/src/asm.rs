@@ -0,0 +1,482 @@
+//! Assembler for the mnemonics printed by the disassembler.
+//!
+//! Each line holds an optional `label:`, an optional address column (as
+//! printed by `listing`, ignored here) and one statement; `;` starts a
+//! comment. Operands are `[a]` (position), `#v` (immediate) or `rb+n`
+//! (relative), where values are integers, character literals like `'\n'`,
+//! label names, or sums and differences of those.
+//!
+//! `data` emits its comma separated values as raw words, with string
+//! literals expanding to one word per character. On top of the real
+//! instructions there are helpers that use the relative base as a stack
+//! pointer to the next free slot: `push v`, `pop dest`, `call target` and
+//! `ret`. `call` labels its return addresses `__ret1`, `__ret2` and so on,
+//! so labels starting with `__ret` are reserved.
+
+use instr::{Opcode, OperandMode};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct AsmError {
+    /// 1-based source line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+enum Item {
+    Instr { op: Opcode, operands: Vec<String> },
+    Data(Vec<String>),
+}
+
+fn split_outside_quotes(text: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if quote.is_some() && c == '\\' {
+            escaped = true;
+        } else if Some(c) == quote {
+            quote = None;
+        } else if quote.is_none() && (c == '\'' || c == '"') {
+            quote = Some(c);
+        } else if quote.is_none() && c == sep {
+            parts.push(&text[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn unescape(c: char) -> Result<char, String> {
+    match c {
+        'n' => Ok('\n'),
+        't' => Ok('\t'),
+        'r' => Ok('\r'),
+        '0' => Ok('\0'),
+        '\\' | '\'' | '"' => Ok(c),
+        _ => Err(format!("unknown escape \\{}", c)),
+    }
+}
+
+/// Decodes the body of a quoted literal, starting just after the opening
+/// quote. Returns the characters and the rest of the text after the
+/// closing quote.
+fn parse_quoted(text: &str, quote: char) -> Result<(Vec<char>, &str), String> {
+    let mut chars = Vec::new();
+    let mut iter = text.char_indices();
+    while let Some((i, c)) = iter.next() {
+        if c == quote {
+            return Ok((chars, &text[i + 1..]));
+        }
+        if c == '\\' {
+            match iter.next() {
+                Some((_, e)) => chars.push(unescape(e)?),
+                None => break,
+            }
+        } else {
+            chars.push(c);
+        }
+    }
+    Err(format!("unterminated literal {}{}", quote, text))
+}
+
+fn eval(expr: &str, labels: &HashMap<String, i64>) -> Result<i64, String> {
+    let mut rest = expr.trim();
+    let mut total: i64 = 0;
+    let mut sign: i64 = 1;
+    let mut expect_term = true;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        if !expect_term {
+            sign = match c {
+                '+' => 1,
+                '-' => -1,
+                _ => return Err(format!("unexpected {:?} in {:?}", c, expr)),
+            };
+            expect_term = true;
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        // Numbers take their sign while parsing so that i64::MIN is
+        // accepted; other terms are negated afterwards.
+        let term = if c == '-' || c == '+' {
+            if c == '-' {
+                sign = -sign;
+            }
+            rest = &rest[1..];
+            continue;
+        } else if c == '\'' {
+            let (chars, after) = parse_quoted(&rest[1..], '\'')?;
+            if chars.len() != 1 {
+                return Err(format!("bad character literal in {:?}", expr));
+            }
+            rest = after;
+            (chars[0] as i64) * sign
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let digits = if sign < 0 {
+                format!("-{}", &rest[..end])
+            } else {
+                rest[..end].to_string()
+            };
+            let value = digits
+                .parse::<i64>()
+                .map_err(|_| format!("bad number in {:?}", expr))?;
+            rest = &rest[end..];
+            value
+        } else if is_ident_start(c) {
+            let end = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+            let name = &rest[..end];
+            rest = &rest[end..];
+            labels
+                .get(name)
+                .ok_or_else(|| format!("unknown label {}", name))?
+                .checked_mul(sign)
+                .ok_or_else(|| format!("overflow in {:?}", expr))?
+        } else {
+            return Err(format!("unexpected {:?} in {:?}", c, expr));
+        };
+        total = total
+            .checked_add(term)
+            .ok_or_else(|| format!("overflow in {:?}", expr))?;
+        sign = 1;
+        expect_term = false;
+    }
+    if expect_term {
+        return Err(format!("missing value in {:?}", expr));
+    }
+    Ok(total)
+}
+
+fn parse_operand(text: &str, labels: &HashMap<String, i64>) -> Result<(OperandMode, i64), String> {
+    let text = text.trim();
+    if text.starts_with('[') && text.ends_with(']') {
+        Ok((
+            OperandMode::Position,
+            eval(&text[1..text.len() - 1], labels)?,
+        ))
+    } else if let Some(rest) = text.strip_prefix('#') {
+        Ok((OperandMode::Immediate, eval(rest, labels)?))
+    } else if text == "rb" {
+        Ok((OperandMode::Relative, 0))
+    } else if text.starts_with("rb+") || text.starts_with("rb-") {
+        Ok((OperandMode::Relative, eval(&text[2..], labels)?))
+    } else {
+        Err(format!("operand {:?} needs a mode: [a], #v or rb+n", text))
+    }
+}
+
+fn data_width(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    if let Some(rest) = value.strip_prefix('"') {
+        let (chars, after) = parse_quoted(rest, '"')?;
+        if !after.trim().is_empty() {
+            return Err(format!("unexpected text after string {:?}", value));
+        }
+        Ok(chars.len())
+    } else {
+        Ok(1)
+    }
+}
+
+/// Prefix of the labels `call` makes for its return addresses.
+const RET_LABEL: &str = "__ret";
+
+fn expand(mnemonic: &str, operands: Vec<String>, call_count: &mut usize) -> Option<Vec<Item>> {
+    let instr = |op, operands: &[&str]| Item::Instr {
+        op,
+        operands: operands.iter().map(|s| s.to_string()).collect(),
+    };
+    let one = operands.first().map(|s| s.as_str()).unwrap_or("");
+    Some(match mnemonic {
+        "push" => vec![
+            instr(Opcode::Add, &[one, "#0", "rb"]),
+            instr(Opcode::AdjustRelBase, &["#1"]),
+        ],
+        "pop" => vec![
+            instr(Opcode::AdjustRelBase, &["#-1"]),
+            instr(Opcode::Add, &["rb", "#0", one]),
+        ],
+        "call" => {
+            *call_count += 1;
+            let ret = format!("#{}{}", RET_LABEL, call_count);
+            vec![
+                instr(Opcode::Add, &[&ret, "#0", "rb"]),
+                instr(Opcode::AdjustRelBase, &["#1"]),
+                instr(Opcode::JumpTrue, &["#1", one]),
+            ]
+        }
+        "ret" => vec![
+            instr(Opcode::AdjustRelBase, &["#-1"]),
+            instr(Opcode::JumpTrue, &["#1", "rb"]),
+        ],
+        _ => return None,
+    })
+}
+
+fn helper_arity(mnemonic: &str) -> Option<usize> {
+    match mnemonic {
+        "push" | "pop" | "call" => Some(1),
+        "ret" => Some(0),
+        _ => None,
+    }
+}
+
+pub fn assemble(src: &str) -> Result<Vec<i64>, AsmError> {
+    let mut labels: HashMap<String, i64> = HashMap::new();
+    let mut items: Vec<(usize, Item)> = Vec::new();
+    let mut addr: i64 = 0;
+    let mut call_count = 0;
+
+    for (i, raw_line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let err = |message: String| AsmError {
+            line: line_no,
+            message,
+        };
+        let mut line = split_outside_quotes(raw_line, ';')[0].trim();
+
+        // labels
+        loop {
+            let end = line.find(|c: char| !is_ident_char(c)).unwrap_or(line.len());
+            let starts_ident = line.chars().next().is_some_and(is_ident_start);
+            if !starts_ident || !line[end..].starts_with(':') {
+                break;
+            }
+            let name = &line[..end];
+            if name == "rb" {
+                return Err(err("rb can't be used as a label".to_string()));
+            }
+            if name.starts_with(RET_LABEL) {
+                return Err(err(format!(
+                    "{}... labels are reserved for call",
+                    RET_LABEL
+                )));
+            }
+            if labels.insert(name.to_string(), addr).is_some() {
+                return Err(err(format!("duplicate label {}", name)));
+            }
+            line = line[end + 1..].trim();
+        }
+
+        // address column from a listing
+        let first_end = line.find(char::is_whitespace).unwrap_or(line.len());
+        if first_end > 0 && line[..first_end].chars().all(|c| c.is_ascii_digit()) {
+            line = line[first_end..].trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let mnemonic_end = line.find(char::is_whitespace).unwrap_or(line.len());
+        let mnemonic = &line[..mnemonic_end];
+        let rest = line[mnemonic_end..].trim();
+        let operands: Vec<String> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            split_outside_quotes(rest, ',')
+                .into_iter()
+                .map(|s| s.trim().to_string())
+                .collect()
+        };
+
+        if mnemonic == "data" {
+            if operands.is_empty() {
+                return Err(err("data needs at least one value".to_string()));
+            }
+            for value in &operands {
+                addr += data_width(value).map_err(&err)? as i64;
+            }
+            items.push((line_no, Item::Data(operands)));
+            continue;
+        }
+
+        let expected = match Opcode::from_mnemonic(mnemonic) {
+            Some(op) => op.operand_count(),
+            None => match helper_arity(mnemonic) {
+                Some(arity) => arity,
+                None => return Err(err(format!("unknown mnemonic {}", mnemonic))),
+            },
+        };
+        if operands.len() != expected {
+            return Err(err(format!(
+                "{} takes {} operands, got {}",
+                mnemonic,
+                expected,
+                operands.len()
+            )));
+        }
+
+        let expanded = match Opcode::from_mnemonic(mnemonic) {
+            Some(op) => vec![Item::Instr { op, operands }],
+            None => expand(mnemonic, operands, &mut call_count).unwrap(),
+        };
+        for item in expanded {
+            if let Item::Instr { op, .. } = item {
+                addr += 1 + op.operand_count() as i64;
+            }
+            items.push((line_no, item));
+        }
+        if mnemonic == "call" {
+            labels.insert(format!("{}{}", RET_LABEL, call_count), addr);
+        }
+    }
+
+    let mut prog = Vec::with_capacity(addr as usize);
+    for (line_no, item) in items {
+        let err = |message: String| AsmError {
+            line: line_no,
+            message,
+        };
+        match item {
+            Item::Instr { op, operands } => {
+                let mut word = op as i64;
+                let mut values = Vec::new();
+                for (i, text) in operands.iter().enumerate() {
+                    let (mode, value) = parse_operand(text, &labels).map_err(&err)?;
+                    if mode == OperandMode::Immediate
+                        && op.writes_last()
+                        && i == op.operand_count() - 1
+                    {
+                        return Err(err(format!("{} can't write to an immediate", text)));
+                    }
+                    word += mode as i64 * 10i64.pow(i as u32 + 2);
+                    values.push(value);
+                }
+                prog.push(word);
+                prog.extend(values);
+            }
+            Item::Data(values) => {
+                for value in values {
+                    if let Some(rest) = value.strip_prefix('"') {
+                        let (chars, _) = parse_quoted(rest, '"').map_err(&err)?;
+                        prog.extend(chars.into_iter().map(|c| c as i64));
+                    } else {
+                        prog.push(eval(&value, &labels).map_err(&err)?);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(prog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use disasm::listing;
+    use machine::{Machine, RunStatus};
+    use program::parse_program;
+
+    #[test]
+    fn encodes_modes_and_literals() {
+        let src = "
+            start:  add [4], #-3, rb+2   ; comment
+                    mul rb-1, #'A', [start]
+                    out #';'
+                    hlt
+        ";
+        assert_eq!(
+            assemble(src),
+            Ok(vec![21001, 4, -3, 2, 1202, -1, 65, 0, 104, 59, 99])
+        );
+    }
+
+    #[test]
+    fn data_and_labels() {
+        let src = "
+                jt #1, #end
+            msg: data \"hi\\n\", 7, msg+1
+            end: hlt
+        ";
+        assert_eq!(assemble(src), Ok(vec![1105, 1, 8, 104, 105, 10, 7, 4, 99]));
+    }
+
+    #[test]
+    fn reports_errors_with_lines() {
+        let err = assemble("hlt\nadd #1, #2, #3\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(assemble("jt #1, #nowhere").is_err());
+        assert!(assemble("out 5").is_err());
+        assert!(assemble("bogus #1").is_err());
+        assert!(assemble("x: hlt\nx: hlt").is_err());
+        for src in &["__ret1: hlt\ncall #f\nf: ret", "call #f\n__ret1: f: ret"] {
+            assert_eq!(
+                assemble(src).unwrap_err().message,
+                "__ret... labels are reserved for call"
+            );
+        }
+        assert_eq!(assemble("out #1\u{a0}+1"), Ok(vec![104, 2]));
+        assert_eq!(assemble("data 9223372036854775807+1").unwrap_err().line, 1);
+        assert!(assemble("data -9223372036854775808-1").is_err());
+    }
+
+    #[test]
+    fn stack_helpers() {
+        // doubles 21 in a subroutine
+        let src = "
+                arb #stack
+                push #21
+                call #double
+                pop [out]
+                out [out]
+                hlt
+            double:
+                pop [tmp]
+                pop [arg]
+                mul [arg], #2, [arg]
+                push [arg]
+                push [tmp]
+                ret
+            out: data 0
+            tmp: data 0
+            arg: data 0
+            stack: data 0
+        ";
+        let mut machine = Machine::new(assemble(src).unwrap());
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(42)));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Halted));
+    }
+
+    #[test]
+    fn round_trips_listing() {
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        assert_eq!(assemble(&listing(&quine)), Ok(quine));
+
+        let weird = vec![1105, 1, 4, 11101, 99, 1199, -7, 3, i64::MIN, i64::MAX];
+        assert_eq!(assemble(&listing(&weird)), Ok(weird));
+
+        let boost = parse_program(include_str!("../../09/input"));
+        assert_eq!(assemble(&listing(&boost)), Ok(boost));
+    }
+}
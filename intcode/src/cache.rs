@@ -0,0 +1,59 @@
+use error::VmError;
+use instr::{decode_word, fetch_instr_with, split_word, Opcode, OperandMode, RawInstr};
+use memory::Memory;
+
+/// Only low memory is cached; nothing runs code from further up.
+const MAX_CACHED_ADDR: usize = 1 << 16;
+
+/// Remembers the decoded opcode word at each address so hot loops skip
+/// splitting it into digits on every step. Operand words are still read
+/// from memory, so only a write to the opcode word itself invalidates an
+/// entry. Entries are four bytes and sized for the program up front, so
+/// code that only runs once pays a store per instruction and little else.
+#[derive(Clone, Debug)]
+pub struct DecodeCache {
+    entries: Vec<Option<(Opcode, [OperandMode; 3])>>,
+}
+
+impl DecodeCache {
+    /// A cache for the first `len` words of memory, where the program is.
+    /// Code run from further up is decoded every time.
+    pub fn new(len: usize) -> DecodeCache {
+        DecodeCache {
+            entries: vec![None; len.min(MAX_CACHED_ADDR)],
+        }
+    }
+
+    /// Splits the opcode word `word` found at `pc`, or returns what it split
+    /// into last time. Invalid words aren't cached.
+    #[inline]
+    pub fn split(&mut self, pc: usize, word: i64) -> Option<(Opcode, [OperandMode; 3])> {
+        if let Some(&Some(decoded)) = self.entries.get(pc) {
+            return Some(decoded);
+        }
+        let decoded = split_word(word)?;
+        if let Some(entry) = self.entries.get_mut(pc) {
+            *entry = Some(decoded);
+        }
+        Some(decoded)
+    }
+
+    pub fn fetch(&mut self, pc: usize, mem: &Memory) -> Result<RawInstr, VmError> {
+        fetch_instr_with(pc, mem, |pc, word| match self.split(pc, word) {
+            Some(decoded) => Ok(decoded),
+            None => decode_word(pc, word),
+        })
+    }
+
+    pub fn invalidate(&mut self, addr: usize) {
+        if let Some(entry) = self.entries.get_mut(addr) {
+            *entry = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for entry in &mut self.entries {
+            *entry = None;
+        }
+    }
+}
@@ -0,0 +1,639 @@
+//! Static control flow analysis of an Intcode program.
+//!
+//! Code is found by following execution from address 0: straight-line
+//! runs, immediate `jt`/`jf` targets and the return sites of calls. A call
+//! is an unconditional immediate jump preceded in the same run by a write
+//! of its return address (the address right after the jump) to a relative
+//! slot; a return is an unconditional jump through a relative operand
+//! after an `arb` with a negative adjustment. Both shapes are what
+//! compiled Intcode and the assembler's `call`/`ret` helpers produce.
+//!
+//! Anything the walk can't see is reported rather than guessed at:
+//! reachable instructions that the program overwrites through a position
+//! operand are marked as self-modified, and words never reached are split
+//! into code that some reachable instruction stores the address of (so it
+//! may be entered through a computed jump) and plain unreachable code.
+
+use disasm::{decode_at, disassemble, format_listing, Decoded, DisasmLine, Operand};
+use instr::{Opcode, OperandMode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EdgeKind {
+    Fallthrough,
+    /// Conditional jump taken.
+    Taken,
+    /// Conditional jump not taken.
+    NotTaken,
+    Jump,
+    Call,
+    /// From a call to the instruction it returns to.
+    CallReturn,
+}
+
+/// How control leaves a block besides its edges.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Exit {
+    /// Only through the edges.
+    Edges,
+    Halt,
+    Return,
+    /// Through a jump whose target isn't known statically.
+    Indirect,
+    /// Runs into a word that doesn't decode.
+    IntoData,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Block {
+    pub start: usize,
+    /// One past the last word of the block.
+    pub end: usize,
+    pub lines: Vec<DisasmLine>,
+    pub edges: Vec<(EdgeKind, usize)>,
+    pub exit: Exit,
+    /// Addresses in the block that reachable code writes to.
+    pub patched: Vec<usize>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Function {
+    pub entry: usize,
+    /// Start addresses of the blocks reachable from the entry without
+    /// following calls.
+    pub blocks: Vec<usize>,
+    pub calls: Vec<usize>,
+    pub returns: bool,
+}
+
+/// A back edge found while walking a function.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Loop {
+    pub function: usize,
+    pub header: usize,
+    pub latch: usize,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub functions: Vec<Function>,
+    pub loops: Vec<Loop>,
+    /// Unreached runs of code `[start, end)` whose start address a
+    /// reachable instruction stores as a constant, so they may be entered
+    /// by a computed jump.
+    pub indirect_only: Vec<(usize, usize)>,
+    /// Unreached runs of code that nothing points at.
+    pub unreachable: Vec<(usize, usize)>,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Condition {
+    Always,
+    Never,
+    Maybe,
+}
+
+fn condition(op: Opcode, test: &Operand) -> Condition {
+    if test.mode != OperandMode::Immediate {
+        return Condition::Maybe;
+    }
+    if (test.value != 0) == (op == Opcode::JumpTrue) {
+        Condition::Always
+    } else {
+        Condition::Never
+    }
+}
+
+fn is_jump(op: Opcode) -> bool {
+    op == Opcode::JumpTrue || op == Opcode::JumpFalse
+}
+
+/// The constant an instruction stores, if both inputs are immediate.
+fn stored_constant(op: Opcode, operands: &[Operand]) -> Option<(i64, Operand)> {
+    let imm = |o: &Operand| {
+        if o.mode == OperandMode::Immediate {
+            Some(o.value)
+        } else {
+            None
+        }
+    };
+    let value = match op {
+        Opcode::Add => imm(&operands[0])?.checked_add(imm(&operands[1])?)?,
+        Opcode::Multiply => imm(&operands[0])?.checked_mul(imm(&operands[1])?)?,
+        _ => return None,
+    };
+    Some((value, operands[2]))
+}
+
+struct Walk {
+    instrs: BTreeMap<usize, (Opcode, Vec<Operand>)>,
+    calls: BTreeMap<usize, usize>,
+    returns: BTreeSet<usize>,
+    into_data: BTreeSet<usize>,
+}
+
+fn walk(prog: &[i64]) -> Walk {
+    let mut w = Walk {
+        instrs: BTreeMap::new(),
+        calls: BTreeMap::new(),
+        returns: BTreeSet::new(),
+        into_data: BTreeSet::new(),
+    };
+    let mut todo = vec![0];
+    while let Some(start) = todo.pop() {
+        // Straight-line run from `start`, remembering what it has done so
+        // far to recognise calls and returns.
+        let mut run: Vec<(Opcode, Vec<Operand>)> = Vec::new();
+        let mut addr = start;
+        let mut prev = None;
+        while !w.instrs.contains_key(&addr) {
+            let (op, operands) = match decode_at(prog, addr) {
+                Some(decoded) => decoded,
+                None => {
+                    if let Some(prev) = prev {
+                        w.into_data.insert(prev);
+                    }
+                    break;
+                }
+            };
+            w.instrs.insert(addr, (op, operands.clone()));
+            prev = Some(addr);
+            let next = addr + 1 + operands.len();
+            if op == Opcode::Halt {
+                break;
+            }
+            if !is_jump(op) {
+                run.push((op, operands));
+                addr = next;
+                continue;
+            }
+
+            let cond = condition(op, &operands[0]);
+            let target = operands[1];
+            if target.mode == OperandMode::Immediate && target.value >= 0 {
+                if cond != Condition::Never {
+                    todo.push(target.value as usize);
+                }
+                let is_call = cond == Condition::Always
+                    && run
+                        .iter()
+                        .any(|&(op, ref operands)| match stored_constant(op, operands) {
+                            Some((value, dest)) => {
+                                value == next as i64 && dest.mode == OperandMode::Relative
+                            }
+                            None => false,
+                        });
+                if is_call {
+                    w.calls.insert(addr, target.value as usize);
+                    todo.push(next);
+                }
+            } else if target.mode == OperandMode::Relative && cond == Condition::Always {
+                let unwinds = run.iter().any(|&(op, ref operands)| {
+                    op == Opcode::AdjustRelBase
+                        && operands[0].mode == OperandMode::Immediate
+                        && operands[0].value < 0
+                });
+                if unwinds {
+                    w.returns.insert(addr);
+                }
+            }
+            if cond == Condition::Always {
+                break;
+            }
+            run.push((op, operands));
+            addr = next;
+        }
+    }
+    w
+}
+
+fn build_blocks(w: &Walk) -> Vec<Block> {
+    let mut leaders: BTreeSet<usize> = BTreeSet::new();
+    leaders.insert(0);
+    for (&addr, &(op, ref operands)) in &w.instrs {
+        if is_jump(op) {
+            leaders.insert(addr + 1 + operands.len());
+            if operands[1].mode == OperandMode::Immediate && operands[1].value >= 0 {
+                leaders.insert(operands[1].value as usize);
+            }
+        }
+    }
+
+    // Constant writes through position operands; anything landing inside
+    // reachable code rewrites it.
+    let mut written: BTreeSet<usize> = BTreeSet::new();
+    for &(op, ref operands) in w.instrs.values() {
+        if op.writes_last() {
+            let dest = operands[operands.len() - 1];
+            if dest.mode == OperandMode::Position && dest.value >= 0 {
+                written.insert(dest.value as usize);
+            }
+        }
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for (&addr, &(op, ref operands)) in &w.instrs {
+        let width = 1 + operands.len();
+        let continues = match blocks.last() {
+            Some(b) => b.end == addr && b.exit == Exit::Edges && b.edges.is_empty(),
+            None => false,
+        };
+        if !continues || leaders.contains(&addr) {
+            blocks.push(Block {
+                start: addr,
+                end: addr,
+                lines: Vec::new(),
+                edges: Vec::new(),
+                exit: Exit::Edges,
+                patched: Vec::new(),
+            });
+        }
+        let block = blocks.last_mut().unwrap();
+        block.end = addr + width;
+        block.lines.push(DisasmLine {
+            addr,
+            decoded: Decoded::Instr {
+                op,
+                operands: operands.clone(),
+            },
+        });
+        block
+            .patched
+            .extend(written.range(addr..addr + width).cloned());
+
+        let next = addr + width;
+        if op == Opcode::Halt {
+            block.exit = Exit::Halt;
+        } else if is_jump(op) {
+            let cond = condition(op, &operands[0]);
+            let target = operands[1];
+            let immediate = target.mode == OperandMode::Immediate && target.value >= 0;
+            match (cond, immediate) {
+                (Condition::Never, _) => block.edges.push((EdgeKind::Fallthrough, next)),
+                (Condition::Always, true) => match w.calls.get(&addr) {
+                    Some(&callee) => {
+                        block.edges.push((EdgeKind::Call, callee));
+                        block.edges.push((EdgeKind::CallReturn, next));
+                    }
+                    None => block.edges.push((EdgeKind::Jump, target.value as usize)),
+                },
+                (Condition::Always, false) => {
+                    block.exit = if w.returns.contains(&addr) {
+                        Exit::Return
+                    } else {
+                        Exit::Indirect
+                    }
+                }
+                (Condition::Maybe, true) => {
+                    block.edges.push((EdgeKind::Taken, target.value as usize));
+                    block.edges.push((EdgeKind::NotTaken, next));
+                }
+                (Condition::Maybe, false) => {
+                    block.exit = Exit::Indirect;
+                    block.edges.push((EdgeKind::NotTaken, next));
+                }
+            }
+        } else if w.into_data.contains(&addr) {
+            block.exit = Exit::IntoData;
+        } else if leaders.contains(&next) && w.instrs.contains_key(&next) {
+            block.edges.push((EdgeKind::Fallthrough, next));
+        }
+    }
+    blocks
+}
+
+/// Builds the control flow graph of `prog`, starting from address 0.
+pub fn build_cfg(prog: &[i64]) -> Cfg {
+    let w = walk(prog);
+    let blocks = build_blocks(&w);
+    let index: BTreeMap<usize, usize> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b.start, i))
+        .collect();
+
+    let mut entries: BTreeSet<usize> = w.calls.values().cloned().collect();
+    entries.insert(0);
+    let mut functions = Vec::new();
+    let mut loops = Vec::new();
+    for &entry in &entries {
+        let mut function = Function {
+            entry,
+            blocks: Vec::new(),
+            calls: Vec::new(),
+            returns: false,
+        };
+        // Iterative DFS; a block still on the stack when reached again is
+        // a loop header.
+        let mut seen: BTreeSet<usize> = BTreeSet::new();
+        let mut on_stack: BTreeSet<usize> = BTreeSet::new();
+        let mut stack: Vec<(usize, usize)> = Vec::new();
+        if index.contains_key(&entry) {
+            seen.insert(entry);
+            on_stack.insert(entry);
+            stack.push((entry, 0));
+        }
+        while let Some(&mut (start, ref mut next_edge)) = stack.last_mut() {
+            let block = &blocks[index[&start]];
+            let successors: Vec<usize> = block
+                .edges
+                .iter()
+                .filter(|&&(kind, _)| kind != EdgeKind::Call)
+                .map(|&(_, to)| to)
+                .collect();
+            if *next_edge < successors.len() {
+                let to = successors[*next_edge];
+                *next_edge += 1;
+                if on_stack.contains(&to) {
+                    loops.push(Loop {
+                        function: entry,
+                        header: to,
+                        latch: start,
+                    });
+                } else if index.contains_key(&to) && seen.insert(to) {
+                    on_stack.insert(to);
+                    stack.push((to, 0));
+                }
+            } else {
+                on_stack.remove(&start);
+                stack.pop();
+            }
+        }
+        for &start in &seen {
+            let block = &blocks[index[&start]];
+            for &(kind, to) in &block.edges {
+                if kind == EdgeKind::Call && !function.calls.contains(&to) {
+                    function.calls.push(to);
+                }
+            }
+            function.returns |= block.exit == Exit::Return;
+        }
+        function.calls.sort();
+        function.blocks = seen.into_iter().collect();
+        functions.push(function);
+    }
+
+    // Everything not covered by a reachable instruction.
+    let mut covered = vec![false; prog.len()];
+    for block in &blocks {
+        for slot in covered.iter_mut().take(block.end).skip(block.start) {
+            *slot = true;
+        }
+    }
+    let constants: BTreeSet<i64> = w
+        .instrs
+        .values()
+        .filter_map(|&(op, ref operands)| stored_constant(op, operands).map(|(v, _)| v))
+        .collect();
+    let mut indirect_only = Vec::new();
+    let mut unreachable = Vec::new();
+    let mut classify = |start: usize, end: usize| {
+        if constants.contains(&(start as i64)) {
+            indirect_only.push((start, end));
+        } else {
+            unreachable.push((start, end));
+        }
+    };
+    let mut addr = 0;
+    while addr < prog.len() {
+        if covered[addr] {
+            addr += 1;
+            continue;
+        }
+        let start = addr;
+        while addr < prog.len() && !covered[addr] {
+            addr += 1;
+        }
+        // Split the gap into runs of code that could execute in sequence.
+        // Data often happens to decode as an instruction or two, so a run
+        // only counts if it has at least two instructions and ends the way
+        // code does: at a halt or unconditional jump, or by falling into
+        // reachable code.
+        let mut run: Option<(usize, usize)> = None;
+        for line in disassemble(&prog[start..addr]) {
+            let line_addr = start + line.addr;
+            let ends_run = match line.decoded {
+                Decoded::Data(_) => {
+                    run = None;
+                    continue;
+                }
+                Decoded::Instr { op, ref operands } => {
+                    op == Opcode::Halt
+                        || (is_jump(op) && condition(op, &operands[0]) == Condition::Always)
+                }
+            };
+            let (run_start, count) = run.unwrap_or((line_addr, 0));
+            run = Some((run_start, count + 1));
+            if ends_run {
+                if count > 0 {
+                    classify(run_start, line_addr + line.width());
+                }
+                run = None;
+            }
+        }
+        if let Some((run_start, count)) = run {
+            if count > 1 && addr < prog.len() {
+                classify(run_start, addr);
+            }
+        }
+    }
+
+    Cfg {
+        blocks,
+        functions,
+        loops,
+        indirect_only,
+        unreachable,
+    }
+}
+
+fn ranges(list: &[(usize, usize)]) -> String {
+    list.iter()
+        .map(|&(start, end)| format!("{}..{}", start, end))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Cfg {
+    /// A plain-text overview: functions, loops and code the walk couldn't
+    /// account for.
+    pub fn summary(&self) -> String {
+        let mut out = String::new();
+        let words: usize = self.blocks.iter().map(|b| b.end - b.start).sum();
+        let _ = writeln!(
+            out,
+            "{} blocks, {} words of reachable code",
+            self.blocks.len(),
+            words
+        );
+        let _ = writeln!(out, "functions:");
+        for f in &self.functions {
+            let _ = write!(out, "  {:>6}  {} blocks", f.entry, f.blocks.len());
+            if !f.calls.is_empty() {
+                let calls: Vec<String> = f.calls.iter().map(|c| c.to_string()).collect();
+                let _ = write!(out, ", calls {}", calls.join(", "));
+            }
+            if f.returns {
+                let _ = write!(out, ", returns");
+            }
+            let _ = writeln!(out);
+        }
+        if !self.loops.is_empty() {
+            let _ = writeln!(out, "loops:");
+            for l in &self.loops {
+                let _ = writeln!(
+                    out,
+                    "  {:>6}  back edge from {} in function {}",
+                    l.header, l.latch, l.function
+                );
+            }
+        }
+        let patched: Vec<String> = self
+            .blocks
+            .iter()
+            .flat_map(|b| b.patched.iter().map(|a| a.to_string()))
+            .collect();
+        if !patched.is_empty() {
+            let _ = writeln!(out, "self-modified: {}", patched.join(", "));
+        }
+        if !self.indirect_only.is_empty() {
+            let _ = writeln!(
+                out,
+                "only reachable indirectly: {}",
+                ranges(&self.indirect_only)
+            );
+        }
+        if !self.unreachable.is_empty() {
+            let _ = writeln!(out, "unreachable: {}", ranges(&self.unreachable));
+        }
+        out
+    }
+
+    /// Graphviz source with one node per block, labelled with its listing.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph cfg {\n");
+        out.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+        let entries: BTreeSet<usize> = self.functions.iter().map(|f| f.entry).collect();
+        for block in &self.blocks {
+            let mut label = String::new();
+            if entries.contains(&block.start) {
+                label.push_str(&format!("function {}\\l", block.start));
+            }
+            for line in format_listing(&block.lines).lines() {
+                label.push_str(&dot_escape(line));
+                label.push_str("\\l");
+            }
+            match block.exit {
+                Exit::Edges => (),
+                Exit::Halt => (),
+                Exit::Return => label.push_str("(return)\\l"),
+                Exit::Indirect => label.push_str("(indirect jump)\\l"),
+                Exit::IntoData => label.push_str("(runs into data)\\l"),
+            }
+            let style = if block.patched.is_empty() {
+                ""
+            } else {
+                ", color=red"
+            };
+            let _ = writeln!(out, "  b{} [label=\"{}\"{}];", block.start, label, style);
+        }
+        for block in &self.blocks {
+            for &(kind, to) in &block.edges {
+                let attrs = match kind {
+                    EdgeKind::Fallthrough | EdgeKind::Jump => "",
+                    EdgeKind::Taken => " [label=\"T\"]",
+                    EdgeKind::NotTaken => " [label=\"F\"]",
+                    EdgeKind::Call => " [style=bold, color=blue]",
+                    EdgeKind::CallReturn => " [style=dashed]",
+                };
+                let _ = writeln!(out, "  b{} -> b{}{};", block.start, to, attrs);
+            }
+        }
+        for &(start, end) in &self.indirect_only {
+            let _ = writeln!(
+                out,
+                "  u{} [label=\"{}..{} only reachable indirectly\", style=dashed];",
+                start, start, end
+            );
+        }
+        for &(start, end) in &self.unreachable {
+            let _ = writeln!(
+                out,
+                "  u{} [label=\"{}..{} unreachable\", style=filled, fillcolor=gray];",
+                start, start, end
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asm::assemble;
+
+    const SRC: &str = "
+            arb #stack
+            add #1, #0, [seed+1]    ; patches the first operand of seed
+    seed:   add #0, #0, [acc]
+            in [count]
+    loop:   call #twice
+            add [count], #-1, [count]
+            jt [count], #loop
+            out [acc]
+            add #hidden, #0, [ptr]
+            jt #1, [ptr]
+    twice:  mul [acc], #2, [acc]
+            ret
+    hidden: out #7
+            hlt
+    dead:   out #1
+            hlt
+    acc:    data 0
+    count:  data 0
+    ptr:    data 0
+    stack:  data 0
+    ";
+
+    #[test]
+    fn functions_loops_and_leftovers() {
+        let prog = assemble(SRC).unwrap();
+        let cfg = build_cfg(&prog);
+        let twice = 37;
+        assert_eq!(prog[twice], 1002);
+        assert_eq!(cfg.functions.len(), 2);
+        assert_eq!(cfg.functions[0].calls, vec![twice]);
+        assert!(!cfg.functions[0].returns);
+        assert_eq!(cfg.functions[1].entry, twice);
+        assert!(cfg.functions[1].returns);
+
+        let header = 12;
+        assert_eq!(cfg.loops.len(), 1);
+        assert_eq!(cfg.loops[0].header, header);
+        assert!(cfg.blocks.iter().any(|b| b.patched == vec![7]));
+
+        assert_eq!(cfg.indirect_only, vec![(46, 49)]);
+        assert_eq!(cfg.unreachable, vec![(49, 52)]);
+        assert!(cfg
+            .to_dot()
+            .contains(&format!("b12 -> b{} [style=bold, color=blue];", twice)));
+    }
+
+    #[test]
+    fn real_program() {
+        let prog = ::program::parse_program(include_str!("../../17/input"));
+        let cfg = build_cfg(&prog);
+        assert!(cfg.functions.len() > 1);
+        assert!(cfg.functions.iter().skip(1).all(|f| f.returns));
+        assert!(cfg
+            .summary()
+            .starts_with(&format!("{} blocks", cfg.blocks.len())));
+    }
+}
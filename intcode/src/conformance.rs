@@ -0,0 +1,282 @@
+//! The example programs from the puzzle texts of days 2, 5 and 9 with
+//! their expected results, for checking any Intcode interpreter.
+//!
+//! Each interpreter in the repo runs the cases its instruction set covers
+//! through `check_conformance`, so they all agree on the published
+//! behaviour.
+
+/// The puzzle whose instruction set a case needs. Each level includes the
+/// ones before it.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Level {
+    /// Add, multiply and halt, position operands only.
+    Day2,
+    /// Input, output, jumps, comparisons and immediate operands.
+    Day5,
+    /// Relative operands and base adjustment, memory beyond the program
+    /// and numbers that don't fit in 32 bits.
+    Day9,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ConformanceCase {
+    pub name: &'static str,
+    pub level: Level,
+    pub program: &'static [i64],
+    pub inputs: &'static [i64],
+    pub outputs: &'static [i64],
+    /// The start of memory after the program halts, where the puzzle text
+    /// gives it.
+    pub memory: Option<&'static [i64]>,
+}
+
+// Outputs 999 for an input below 8, 1000 for 8 and 1001 above it.
+const CMP8: &[i64] = &[
+    3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0,
+    1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105,
+    1, 46, 98, 99,
+];
+
+const QUINE: &[i64] = &[
+    109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+];
+
+fn case(
+    name: &'static str,
+    level: Level,
+    program: &'static [i64],
+    inputs: &'static [i64],
+    outputs: &'static [i64],
+) -> ConformanceCase {
+    ConformanceCase {
+        name,
+        level,
+        program,
+        inputs,
+        outputs,
+        memory: None,
+    }
+}
+
+fn memory_case(
+    name: &'static str,
+    level: Level,
+    program: &'static [i64],
+    memory: &'static [i64],
+) -> ConformanceCase {
+    ConformanceCase {
+        memory: Some(memory),
+        ..case(name, level, program, &[], &[])
+    }
+}
+
+pub fn conformance_cases() -> Vec<ConformanceCase> {
+    use self::Level::*;
+    vec![
+        memory_case(
+            "day 2 walkthrough",
+            Day2,
+            &[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50],
+            &[3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50],
+        ),
+        memory_case("1 + 1", Day2, &[1, 0, 0, 0, 99], &[2, 0, 0, 0, 99]),
+        memory_case("3 * 2", Day2, &[2, 3, 0, 3, 99], &[2, 3, 0, 6, 99]),
+        memory_case(
+            "99 * 99",
+            Day2,
+            &[2, 4, 4, 5, 99, 0],
+            &[2, 4, 4, 5, 99, 9801],
+        ),
+        memory_case(
+            "overwrites halt",
+            Day2,
+            &[1, 1, 1, 4, 99, 5, 6, 0, 99],
+            &[30, 1, 1, 4, 2, 5, 6, 0, 99],
+        ),
+        case("echo", Day5, &[3, 0, 4, 0, 99], &[-7], &[-7]),
+        memory_case(
+            "immediate multiply",
+            Day5,
+            &[1002, 4, 3, 4, 33],
+            &[1002, 4, 3, 4, 99],
+        ),
+        memory_case(
+            "negative immediate",
+            Day5,
+            &[1101, 100, -1, 4, 0],
+            &[1101, 100, -1, 4, 99],
+        ),
+        case(
+            "equal to 8, position",
+            Day5,
+            &[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8],
+            &[8],
+            &[1],
+        ),
+        case(
+            "not equal to 8, position",
+            Day5,
+            &[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8],
+            &[5],
+            &[0],
+        ),
+        case(
+            "less than 8, position",
+            Day5,
+            &[3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8],
+            &[5],
+            &[1],
+        ),
+        case(
+            "not less than 8, position",
+            Day5,
+            &[3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8],
+            &[8],
+            &[0],
+        ),
+        case(
+            "equal to 8, immediate",
+            Day5,
+            &[3, 3, 1108, -1, 8, 3, 4, 3, 99],
+            &[8],
+            &[1],
+        ),
+        case(
+            "not equal to 8, immediate",
+            Day5,
+            &[3, 3, 1108, -1, 8, 3, 4, 3, 99],
+            &[9],
+            &[0],
+        ),
+        case(
+            "less than 8, immediate",
+            Day5,
+            &[3, 3, 1107, -1, 8, 3, 4, 3, 99],
+            &[-3],
+            &[1],
+        ),
+        case(
+            "not less than 8, immediate",
+            Day5,
+            &[3, 3, 1107, -1, 8, 3, 4, 3, 99],
+            &[12],
+            &[0],
+        ),
+        case(
+            "jump on zero, position",
+            Day5,
+            &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9],
+            &[0],
+            &[0],
+        ),
+        case(
+            "jump on nonzero, position",
+            Day5,
+            &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9],
+            &[4],
+            &[1],
+        ),
+        case(
+            "jump on zero, immediate",
+            Day5,
+            &[3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+            &[0],
+            &[0],
+        ),
+        case(
+            "jump on nonzero, immediate",
+            Day5,
+            &[3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+            &[-2],
+            &[1],
+        ),
+        case("below 8", Day5, CMP8, &[7], &[999]),
+        case("at 8", Day5, CMP8, &[8], &[1000]),
+        case("above 8", Day5, CMP8, &[9], &[1001]),
+        case("quine", Day9, QUINE, &[], QUINE),
+        case(
+            "16 digit product",
+            Day9,
+            &[1102, 34915192, 34915192, 7, 4, 7, 99, 0],
+            &[],
+            &[1219070632396864],
+        ),
+        case(
+            "large number",
+            Day9,
+            &[104, 1125899906842624, 99],
+            &[],
+            &[1125899906842624],
+        ),
+    ]
+}
+
+/// Runs every case up to `level` through `run`, which is given a program
+/// and its inputs and returns the outputs and final memory when it halts.
+/// Returns how many cases passed, or a description of the first failure.
+pub fn check_conformance<F>(level: Level, mut run: F) -> Result<usize, String>
+where
+    F: FnMut(&[i64], &[i64]) -> Result<(Vec<i64>, Vec<i64>), String>,
+{
+    let cases: Vec<ConformanceCase> = conformance_cases()
+        .into_iter()
+        .filter(|c| c.level <= level)
+        .collect();
+    for c in &cases {
+        let (outputs, memory) =
+            run(c.program, c.inputs).map_err(|e| format!("{}: {}", c.name, e))?;
+        if outputs != c.outputs {
+            return Err(format!(
+                "{}: output {:?}, expected {:?}",
+                c.name, outputs, c.outputs
+            ));
+        }
+        if let Some(expected) = c.memory {
+            if memory.get(..expected.len()) != Some(expected) {
+                return Err(format!(
+                    "{}: memory {:?}, expected {:?}",
+                    c.name, memory, expected
+                ));
+            }
+        }
+    }
+    Ok(cases.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::IterInput;
+    use machine::{Machine, RunStatus};
+
+    #[test]
+    fn machine_conforms() {
+        for &cache in &[true, false] {
+            let res = check_conformance(Level::Day9, |prog, inputs| {
+                let mut machine = Machine::new(prog.to_vec());
+                machine.set_decode_cache(cache);
+                let mut out = Vec::new();
+                let mut input = IterInput(inputs.iter().cloned());
+                match machine.run(&mut input, &mut out) {
+                    Ok(RunStatus::Halted) => Ok((out, machine.memory().dense().to_vec())),
+                    Ok(status) => Err(format!("{:?}", status)),
+                    Err(e) => Err(e.to_string()),
+                }
+            });
+            assert_eq!(res, Ok(conformance_cases().len()));
+        }
+    }
+
+    #[test]
+    fn reports_failures() {
+        let res = check_conformance(Level::Day2, |prog, _| Ok((Vec::new(), prog.to_vec())));
+        assert_eq!(
+            res,
+            Err(
+                "day 2 walkthrough: memory [1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50], \
+                 expected [3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]"
+                    .to_string()
+            )
+        );
+    }
+}
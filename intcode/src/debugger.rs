@@ -0,0 +1,326 @@
+//! A line-oriented debugger that sits between a peripheral driver and its
+//! `Machine`. Drivers call `Debugger::run_until_output` or `Debugger::run`
+//! in place of the machine methods of the same name; the debugger prompts
+//! before the first instruction and whenever a breakpoint or watchpoint is
+//! hit, and hands every other status back to the driver untouched.
+
+use disasm::{decode_at, format_listing, Decoded, DisasmLine};
+use error::VmError;
+use io::{Input, Output};
+use machine::{Machine, RunStatus};
+use std::io::{self, BufRead, Stderr, StdinLock, Write};
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute n instructions (default 1)
+  c, continue          run until the next breakpoint or watchpoint
+  b, break [addr]      set a breakpoint, or list them
+  clear addr           remove a breakpoint
+  w, watch [addr]      stop after writes to addr, or list watchpoints
+  unwatch addr         remove a watchpoint
+  r, regs              print prog_count and rel_base
+  x, dump addr [len]   print len words of memory starting at addr
+  poke addr value      write value to addr
+  q, quit              remove all breakpoints and watchpoints and run on
+an empty line repeats the previous command";
+
+const DUMP_WIDTH: usize = 8;
+
+enum Prompt {
+    /// Leave the prompt and let the machine run freely.
+    Resume,
+    /// A stepped instruction produced a status the driver has to see.
+    Event(RunStatus),
+}
+
+pub struct Debugger<R, W> {
+    commands: R,
+    out: W,
+    stepping: bool,
+    last_command: String,
+}
+
+impl Debugger<StdinLock<'static>, Stderr> {
+    /// Reads commands from stdin and talks on stderr, leaving stdout to the
+    /// driver.
+    pub fn stdio() -> Debugger<StdinLock<'static>, Stderr> {
+        Debugger::new(io::stdin().lock(), io::stderr())
+    }
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    pub fn new(commands: R, out: W) -> Debugger<R, W> {
+        Debugger {
+            commands,
+            out,
+            stepping: true,
+            last_command: String::new(),
+        }
+    }
+
+    /// Debugged equivalent of `Machine::run_until_output`.
+    pub fn run_until_output(&mut self, machine: &mut Machine) -> Result<RunStatus, VmError> {
+        loop {
+            if self.stepping {
+                if let Prompt::Event(status) = self.prompt(machine, &mut || None)? {
+                    return Ok(status);
+                }
+            }
+            let status = machine.run_until_output()?;
+            if !self.stopped(status) {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Debugged equivalent of `Machine::run`.
+    pub fn run<I, O>(
+        &mut self,
+        machine: &mut Machine,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<RunStatus, VmError>
+    where
+        I: Input,
+        O: Output,
+    {
+        loop {
+            if self.stepping {
+                match self.prompt(machine, input)? {
+                    Prompt::Event(RunStatus::Output(out)) => {
+                        output.write(out);
+                        continue;
+                    }
+                    Prompt::Event(status) => return Ok(status),
+                    Prompt::Resume => (),
+                }
+            }
+            let status = machine.run(input, output)?;
+            if !self.stopped(status) {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Reports a breakpoint or watchpoint hit and arranges for the next
+    /// loop iteration to prompt. Returns false for any other status.
+    fn stopped(&mut self, status: RunStatus) -> bool {
+        match status {
+            RunStatus::BreakpointHit(addr) => self.say(&format!("breakpoint at {}", addr)),
+            RunStatus::WatchpointHit { addr, old, new } => {
+                self.say(&format!("watchpoint {}: {} -> {}", addr, old, new))
+            }
+            _ => return false,
+        }
+        self.stepping = true;
+        true
+    }
+
+    fn say(&mut self, text: &str) {
+        let _ = writeln!(self.out, "{}", text);
+    }
+
+    fn show_location(&mut self, machine: &Machine) {
+        let addr = machine.state().prog_count;
+        let words = machine.memory().words(addr, 4);
+        let decoded = match decode_at(&words, 0) {
+            Some((op, operands)) => Decoded::Instr { op, operands },
+            None => Decoded::Data(words[0]),
+        };
+        let listing = format_listing(&[DisasmLine { addr, decoded }]);
+        let _ = write!(self.out, "{}", listing);
+    }
+
+    fn prompt<I>(&mut self, machine: &mut Machine, input: &mut I) -> Result<Prompt, VmError>
+    where
+        I: Input,
+    {
+        self.show_location(machine);
+        loop {
+            let _ = write!(self.out, "(icdb) ");
+            let _ = self.out.flush();
+            let mut line = String::new();
+            match self.commands.read_line(&mut line) {
+                Ok(0) | Err(_) => return Ok(self.detach(machine)),
+                Ok(_) => (),
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                text => text.to_string(),
+            };
+            self.last_command = line.clone();
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let args: Vec<Option<i64>> = words.iter().skip(1).map(|w| w.parse().ok()).collect();
+            let addr = |i: usize| match args.get(i) {
+                Some(&Some(a)) if a >= 0 => Some(a as usize),
+                _ => None,
+            };
+            match (words.first().cloned().unwrap_or(""), args.len()) {
+                ("s", _) | ("step", _) => {
+                    let count = addr(0).unwrap_or(1);
+                    if let Some(status) = self.step(machine, input, count) {
+                        return Ok(Prompt::Event(status));
+                    }
+                    self.show_location(machine);
+                }
+                ("c", 0) | ("continue", 0) => {
+                    self.stepping = false;
+                    return Ok(Prompt::Resume);
+                }
+                ("b", 0) | ("break", 0) => {
+                    let list = format!("breakpoints: {:?}", machine.breakpoints());
+                    self.say(&list);
+                }
+                ("b", 1) | ("break", 1) => match addr(0) {
+                    Some(a) => machine.add_breakpoint(a),
+                    None => self.say("bad address"),
+                },
+                ("clear", 1) => match addr(0) {
+                    Some(a) if machine.remove_breakpoint(a) => (),
+                    _ => self.say("no such breakpoint"),
+                },
+                ("w", 0) | ("watch", 0) => {
+                    let list = format!("watchpoints: {:?}", machine.watchpoints());
+                    self.say(&list);
+                }
+                ("w", 1) | ("watch", 1) => match addr(0) {
+                    Some(a) => machine.add_watchpoint(a),
+                    None => self.say("bad address"),
+                },
+                ("unwatch", 1) => match addr(0) {
+                    Some(a) if machine.remove_watchpoint(a) => (),
+                    _ => self.say("no such watchpoint"),
+                },
+                ("r", 0) | ("regs", 0) => {
+                    let state = machine.state();
+                    self.say(&format!(
+                        "prog_count {} rel_base {}",
+                        state.prog_count, state.rel_base
+                    ));
+                }
+                ("x", 1) | ("x", 2) | ("dump", 1) | ("dump", 2) => {
+                    match (
+                        addr(0),
+                        args.get(1).cloned().unwrap_or(Some(DUMP_WIDTH as i64)),
+                    ) {
+                        (Some(start), Some(len)) if len >= 0 => {
+                            self.dump(machine, start, len as usize)
+                        }
+                        _ => self.say("bad range"),
+                    }
+                }
+                ("poke", 2) => match (addr(0), args[1]) {
+                    (Some(a), Some(value)) => {
+                        if let Err(e) = machine.write(a, value) {
+                            self.say(&e.to_string());
+                        }
+                    }
+                    _ => self.say("bad address or value"),
+                },
+                ("q", 0) | ("quit", 0) => return Ok(self.detach(machine)),
+                ("h", _) | ("help", _) => self.say(HELP),
+                _ => self.say("unknown command, try help"),
+            }
+        }
+    }
+
+    /// Executes up to `count` instructions, stopping early on anything the
+    /// user or the driver needs to know about. Faults are reported and
+    /// leave the machine on the faulting instruction so memory can be
+    /// patched before retrying.
+    fn step<I>(&mut self, machine: &mut Machine, input: &mut I, count: usize) -> Option<RunStatus>
+    where
+        I: Input,
+    {
+        for _ in 0..count {
+            match machine.step_with(input) {
+                Ok(None) => (),
+                Ok(Some(RunStatus::WatchpointHit { addr, old, new })) => {
+                    self.say(&format!("watchpoint {}: {} -> {}", addr, old, new));
+                    return None;
+                }
+                Ok(Some(status)) => {
+                    self.say(&format!("{:?}", status));
+                    return Some(status);
+                }
+                Err(e) => {
+                    self.say(&format!("fault: {}", e));
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    fn dump(&mut self, machine: &Machine, start: usize, len: usize) {
+        let mut addr = start;
+        while addr < start + len {
+            let end = (addr + DUMP_WIDTH).min(start + len);
+            let words: Vec<String> = (addr..end)
+                .map(|a| machine.read(a).map_or("?".to_string(), |v| v.to_string()))
+                .collect();
+            self.say(&format!("{:>6}: {}", addr, words.join(" ")));
+            addr = end;
+        }
+    }
+
+    /// Drops every breakpoint and watchpoint and stops prompting.
+    fn detach(&mut self, machine: &mut Machine) -> Prompt {
+        let breakpoints: Vec<usize> = machine.breakpoints().iter().cloned().collect();
+        for addr in breakpoints {
+            machine.remove_breakpoint(addr);
+        }
+        let watchpoints: Vec<usize> = machine.watchpoints().iter().cloned().collect();
+        for addr in watchpoints {
+            machine.remove_watchpoint(addr);
+        }
+        self.stepping = false;
+        Prompt::Resume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Adds 2 and 3 into address 7, prints it and halts.
+    const PROG: [i64; 8] = [1101, 2, 3, 7, 4, 7, 99, 0];
+
+    fn session(commands: &str) -> (Machine, Vec<RunStatus>, String) {
+        let mut machine = Machine::new(PROG.to_vec());
+        let mut transcript = Vec::new();
+        let mut statuses = Vec::new();
+        {
+            let mut debugger = Debugger::new(commands.as_bytes(), &mut transcript);
+            loop {
+                let status = debugger.run_until_output(&mut machine).unwrap();
+                statuses.push(status);
+                if status == RunStatus::Halted {
+                    break;
+                }
+            }
+        }
+        (machine, statuses, String::from_utf8(transcript).unwrap())
+    }
+
+    #[test]
+    fn breakpoint_then_inspect() {
+        let (_, statuses, transcript) = session("b 4\nc\nregs\nx 4 4\nc\n");
+        assert_eq!(statuses, vec![RunStatus::Output(5), RunStatus::Halted]);
+        assert!(transcript.contains("breakpoint at 4"));
+        assert!(transcript.contains("     4  out [7]"));
+        assert!(transcript.contains("prog_count 4 rel_base 0"));
+        assert!(transcript.contains("     4: 4 7 99 5"));
+    }
+
+    #[test]
+    fn step_poke_and_watch() {
+        let (machine, statuses, transcript) = session("w 7\nc\npoke 7 40\nregs\n\ns\nq\n");
+        assert_eq!(statuses, vec![RunStatus::Output(40), RunStatus::Halted]);
+        assert!(transcript.contains("watchpoint 7: 0 -> 5"));
+        assert_eq!(transcript.matches("prog_count 4 rel_base 0").count(), 2);
+        assert!(transcript.contains("Output(40)"));
+        assert!(machine.watchpoints().is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use instr::{operand_modes, Opcode, OperandMode};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// A raw operand word together with the mode it's interpreted in.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Operand {
+    pub mode: OperandMode,
+    pub value: i64,
+}
+
+/// Position operands print as `[a]`, immediates as `#imm` and relative
+/// operands as `rb+n`.
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            OperandMode::Position => write!(f, "[{}]", self.value),
+            OperandMode::Immediate => write!(f, "#{}", self.value),
+            OperandMode::Relative if self.value < 0 => write!(f, "rb{}", self.value),
+            OperandMode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Decoded {
+    Instr { op: Opcode, operands: Vec<Operand> },
+    Data(i64),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DisasmLine {
+    pub addr: usize,
+    pub decoded: Decoded,
+}
+
+impl DisasmLine {
+    /// Number of words the line covers.
+    pub fn width(&self) -> usize {
+        match self.decoded {
+            Decoded::Instr { ref operands, .. } => 1 + operands.len(),
+            Decoded::Data(_) => 1,
+        }
+    }
+
+    /// The address an immediate jump on this line goes to, if any.
+    pub fn jump_target(&self) -> Option<usize> {
+        match self.decoded {
+            Decoded::Instr {
+                op: Opcode::JumpTrue,
+                ref operands,
+            }
+            | Decoded::Instr {
+                op: Opcode::JumpFalse,
+                ref operands,
+            } if operands[1].mode == OperandMode::Immediate && operands[1].value >= 0 => {
+                Some(operands[1].value as usize)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the instruction starting at `addr`. Returns `None` for words
+/// that can't start an instruction: unknown opcodes or modes, immediate
+/// write targets, mode digits on operands the opcode doesn't have, or
+/// operands running past the end of the program.
+pub fn decode_at(prog: &[i64], addr: usize) -> Option<(Opcode, Vec<Operand>)> {
+    let word = *prog.get(addr)?;
+    if word < 0 {
+        return None;
+    }
+    let op = Opcode::from_i64(word % 100).ok()?;
+    let modes = operand_modes(word / 100).ok()?;
+    let count = op.operand_count();
+    if modes[count..].iter().any(|&m| m != OperandMode::Position) {
+        return None;
+    }
+    if op.writes_last() && modes[count - 1] == OperandMode::Immediate {
+        return None;
+    }
+    let operands = (0..count)
+        .map(|i| {
+            prog.get(addr + 1 + i).map(|&value| Operand {
+                mode: modes[i],
+                value,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((op, operands))
+}
+
+/// Linear sweep over the program, decoding instructions where possible
+/// and falling back to one data word at a time.
+pub fn disassemble(prog: &[i64]) -> Vec<DisasmLine> {
+    let mut lines = Vec::new();
+    let mut addr = 0;
+    while addr < prog.len() {
+        let decoded = match decode_at(prog, addr) {
+            Some((op, operands)) => Decoded::Instr { op, operands },
+            None => Decoded::Data(prog[addr]),
+        };
+        let line = DisasmLine { addr, decoded };
+        addr += line.width();
+        lines.push(line);
+    }
+    lines
+}
+
+pub fn label_name(addr: usize) -> String {
+    format!("L{}", addr)
+}
+
+/// Renders `lines` as assembler source: one line per instruction or data
+/// word prefixed with its address, and a label before every line that an
+/// immediate jump targets.
+pub fn format_listing(lines: &[DisasmLine]) -> String {
+    let starts: BTreeSet<usize> = lines.iter().map(|l| l.addr).collect();
+    let labels: BTreeSet<usize> = lines
+        .iter()
+        .filter_map(|l| l.jump_target())
+        .filter(|t| starts.contains(t))
+        .collect();
+
+    let mut out = String::new();
+    for line in lines {
+        if labels.contains(&line.addr) {
+            out.push_str(&format!("{}:\n", label_name(line.addr)));
+        }
+        let text = match line.decoded {
+            Decoded::Instr { op, ref operands } => {
+                let target = line.jump_target().filter(|t| labels.contains(t));
+                let ops = operands
+                    .iter()
+                    .enumerate()
+                    .map(|(i, o)| match target {
+                        Some(t) if i == 1 => format!("#{}", label_name(t)),
+                        _ => o.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                if ops.is_empty() {
+                    op.mnemonic().to_string()
+                } else {
+                    format!("{} {}", op.mnemonic(), ops.join(", "))
+                }
+            }
+            Decoded::Data(value) => format!("data {}", value),
+        };
+        out.push_str(&format!("{:>6}  {}\n", line.addr, text));
+    }
+    out
+}
+
+pub fn listing(prog: &[i64]) -> String {
+    format_listing(&disassemble(prog))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_modes_labels_and_data() {
+        let prog = vec![109, -1, 1105, 1, 7, 11101, 99, 4, 3, 1199];
+        let expected = [
+            "     0  arb #-1",
+            "     2  jt #1, #L7",
+            "     5  data 11101",
+            "     6  hlt",
+            "L7:",
+            "     7  out [3]",
+            "     9  data 1199",
+        ];
+        assert_eq!(listing(&prog), expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn relative_operands() {
+        let prog = vec![22201, 1, -2, 3];
+        assert_eq!(listing(&prog), "     0  add rb+1, rb-2, rb+3\n");
+    }
+
+    #[test]
+    fn truncated_instruction_is_data() {
+        let prog = vec![1, 0, 0];
+        let lines = disassemble(&prog);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].decoded, Decoded::Data(1));
+    }
+}
@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::fmt;
+
+/// A fault raised while executing an Intcode program. Every variant records
+/// the address of the faulting instruction and its raw (undecoded) word.
+#[derive(Clone, PartialEq, Debug)]
+pub enum VmError {
+    InvalidOpcode {
+        pc: usize,
+        instr: i64,
+    },
+    InvalidOperandMode {
+        pc: usize,
+        instr: i64,
+    },
+    ImmediateWrite {
+        pc: usize,
+        instr: i64,
+    },
+    NegativeAddress {
+        pc: usize,
+        instr: i64,
+        addr: i64,
+    },
+    UnparsableInput {
+        pc: usize,
+        instr: i64,
+        input: String,
+    },
+    AddressTooLarge {
+        pc: usize,
+        instr: i64,
+        addr: usize,
+    },
+    ArithmeticOverflow {
+        pc: usize,
+        instr: i64,
+    },
+}
+
+impl VmError {
+    pub fn pc(&self) -> usize {
+        match *self {
+            VmError::InvalidOpcode { pc, .. }
+            | VmError::InvalidOperandMode { pc, .. }
+            | VmError::ImmediateWrite { pc, .. }
+            | VmError::NegativeAddress { pc, .. }
+            | VmError::UnparsableInput { pc, .. }
+            | VmError::AddressTooLarge { pc, .. }
+            | VmError::ArithmeticOverflow { pc, .. } => pc,
+        }
+    }
+
+    pub fn instr(&self) -> i64 {
+        match *self {
+            VmError::InvalidOpcode { instr, .. }
+            | VmError::InvalidOperandMode { instr, .. }
+            | VmError::ImmediateWrite { instr, .. }
+            | VmError::NegativeAddress { instr, .. }
+            | VmError::UnparsableInput { instr, .. }
+            | VmError::AddressTooLarge { instr, .. }
+            | VmError::ArithmeticOverflow { instr, .. } => instr,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VmError::InvalidOpcode { .. } => write!(f, "invalid opcode")?,
+            VmError::InvalidOperandMode { .. } => write!(f, "invalid operand mode")?,
+            VmError::ImmediateWrite { .. } => write!(f, "immediate mode write target")?,
+            VmError::NegativeAddress { addr, .. } => write!(f, "negative address {}", addr)?,
+            VmError::UnparsableInput { ref input, .. } => {
+                write!(f, "unparsable input {:?}", input)?
+            }
+            VmError::AddressTooLarge { addr, .. } => {
+                write!(f, "address {} beyond memory limit", addr)?
+            }
+            VmError::ArithmeticOverflow { .. } => write!(f, "arithmetic overflow")?,
+        }
+        write!(f, " at pc {} (instruction {})", self.pc(), self.instr())
+    }
+}
+
+impl Error for VmError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_includes_location() {
+        let err = VmError::NegativeAddress {
+            pc: 4,
+            instr: 1001,
+            addr: -3,
+        };
+        assert_eq!(
+            err.to_string(),
+            "negative address -3 at pc 4 (instruction 1001)"
+        );
+    }
+}
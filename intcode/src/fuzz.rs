@@ -0,0 +1,455 @@
+//! Differential fuzzing: random programs run on several interpreters,
+//! which must agree on the outputs, the final memory and whether the
+//! program halted.
+//!
+//! Programs are generated as a list of instructions followed by a halt and
+//! some data words. Writes only ever go to the data or beyond the end of
+//! the program and every jump goes forward to the start of a later
+//! instruction, so each program finishes in at most one pass over its
+//! code. That stays true while a failing case is shrunk, since shrinking
+//! works on the instruction list rather than the raw words.
+
+use conformance::Level;
+use instr::Opcode;
+use io::IterInput;
+use machine::{Machine, RunStatus};
+use std::fmt;
+
+/// What running a program produced. `memory` covers the program's own
+/// words, and is only compared when both runs halted.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Outcome {
+    pub outputs: Vec<i64>,
+    pub memory: Vec<i64>,
+    pub halted: bool,
+}
+
+/// Runs a program on a fresh `Machine`, for use as the reference
+/// interpreter.
+pub fn machine_outcome(prog: &[i64], inputs: &[i64]) -> Outcome {
+    let mut machine = Machine::new(prog.to_vec());
+    let mut outputs = Vec::new();
+    let status = machine.run(&mut IterInput(inputs.iter().cloned()), &mut outputs);
+    Outcome {
+        outputs,
+        memory: machine.memory().words(0, prog.len()),
+        halted: status == Ok(RunStatus::Halted),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct FuzzConfig {
+    pub seed: u64,
+    /// How many programs to try.
+    pub cases: usize,
+    /// Instructions per program, not counting the final halt.
+    pub instructions: usize,
+    /// Which instructions and operand modes to use. Relative mode is never
+    /// generated.
+    pub level: Level,
+    /// Also generate immediate mode writes and addresses past the end of
+    /// the program, which interpreters are most likely to disagree on.
+    pub edge_cases: bool,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> FuzzConfig {
+        FuzzConfig {
+            seed: 1,
+            cases: 500,
+            instructions: 12,
+            level: Level::Day5,
+            edge_cases: false,
+        }
+    }
+}
+
+/// A program on which the interpreters disagreed, shrunk as far as it
+/// would go while they still do.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Mismatch {
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+    /// Every interpreter's outcome, by name.
+    pub outcomes: Vec<(String, Outcome)>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "program {:?}", self.program)?;
+        writeln!(f, "inputs {:?}", self.inputs)?;
+        for (name, outcome) in &self.outcomes {
+            writeln!(
+                f,
+                "{}: outputs {:?}, {}, memory {:?}",
+                name,
+                outcome.outputs,
+                if outcome.halted { "halted" } else { "faulted" },
+                outcome.memory
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// An interpreter under test: given a program and its inputs, runs it to
+/// the end.
+pub type Runner<'a> = (&'a str, &'a mut dyn FnMut(&[i64], &[i64]) -> Outcome);
+
+/// xorshift64*; plenty for picking instructions and reproducible from the
+/// seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next() % (hi - lo + 1) as u64) as i64
+    }
+
+    fn chance(&mut self, one_in: usize) -> bool {
+        self.below(one_in) == 0
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Arg {
+    Imm(i64),
+    /// Address of a data word.
+    Data(usize),
+    /// An address this far past the end of the program.
+    Far(usize),
+    /// Start of an instruction, or of the final halt.
+    Label(usize),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Instr {
+    op: Opcode,
+    args: Vec<Arg>,
+    /// Gives the written operand immediate mode.
+    immediate_write: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Case {
+    code: Vec<Instr>,
+    data: Vec<i64>,
+    inputs: Vec<i64>,
+}
+
+const DATA_WORDS: usize = 6;
+
+impl Case {
+    fn generate(rng: &mut Rng, config: &FuzzConfig) -> Case {
+        let day5 = config.level >= Level::Day5;
+        let ops: &[Opcode] = if day5 {
+            &[
+                Opcode::Add,
+                Opcode::Multiply,
+                Opcode::Input,
+                Opcode::Output,
+                Opcode::JumpTrue,
+                Opcode::JumpFalse,
+                Opcode::LessThan,
+                Opcode::Equals,
+            ]
+        } else {
+            &[Opcode::Add, Opcode::Multiply]
+        };
+        let (lo, hi) = if day5 { (-100, 100) } else { (0, 100) };
+        let n = config.instructions;
+        let mut inputs = Vec::new();
+        let code = (0..n)
+            .map(|i| {
+                let op = ops[rng.below(ops.len())];
+                let read = |rng: &mut Rng| {
+                    if config.edge_cases && rng.chance(8) {
+                        Arg::Far(rng.below(100))
+                    } else if day5 && rng.chance(2) {
+                        Arg::Imm(rng.range(lo, hi))
+                    } else {
+                        Arg::Data(rng.below(DATA_WORDS))
+                    }
+                };
+                let args = match op {
+                    Opcode::JumpTrue | Opcode::JumpFalse => {
+                        vec![read(rng), Arg::Label(i + 1 + rng.below(n - i))]
+                    }
+                    Opcode::Output => vec![read(rng)],
+                    _ => {
+                        let mut args = Vec::new();
+                        if op != Opcode::Input {
+                            args.push(read(rng));
+                            args.push(read(rng));
+                        } else {
+                            inputs.push(rng.range(lo, hi));
+                        }
+                        args.push(if config.edge_cases && rng.chance(8) {
+                            Arg::Far(rng.below(100))
+                        } else {
+                            Arg::Data(rng.below(DATA_WORDS))
+                        });
+                        args
+                    }
+                };
+                Instr {
+                    op,
+                    args,
+                    immediate_write: op.writes_last() && day5 && config.edge_cases && rng.chance(8),
+                }
+            })
+            .collect();
+        let data = (0..DATA_WORDS).map(|_| rng.range(lo, hi)).collect();
+        Case { code, data, inputs }
+    }
+
+    fn program(&self) -> Vec<i64> {
+        let mut starts = Vec::new();
+        let mut addr = 0;
+        for instr in &self.code {
+            starts.push(addr);
+            addr += 1 + instr.args.len();
+        }
+        starts.push(addr);
+        let data_start = addr + 1;
+        let end = data_start + self.data.len();
+
+        let mut words = Vec::new();
+        for instr in &self.code {
+            let mut word = instr.op as i64;
+            let mut digit = 100;
+            for (i, &arg) in instr.args.iter().enumerate() {
+                let last_write = i == instr.args.len() - 1 && instr.op.writes_last();
+                let immediate = match arg {
+                    Arg::Imm(_) | Arg::Label(_) => true,
+                    Arg::Data(_) | Arg::Far(_) => last_write && instr.immediate_write,
+                };
+                if immediate {
+                    word += digit;
+                }
+                digit *= 10;
+            }
+            words.push(word);
+            for &arg in &instr.args {
+                words.push(match arg {
+                    Arg::Imm(v) => v,
+                    Arg::Data(k) => (data_start + k) as i64,
+                    Arg::Far(k) => (end + k) as i64,
+                    Arg::Label(j) => starts[j] as i64,
+                });
+            }
+        }
+        words.push(99);
+        words.extend_from_slice(&self.data);
+        words
+    }
+
+    /// Smaller or simpler variants of the case, most drastic first.
+    fn shrinks(&self) -> Vec<Case> {
+        let mut out = Vec::new();
+        for i in 0..self.code.len() {
+            let mut c = self.clone();
+            c.code.remove(i);
+            // A jump to the removed instruction lands on the one after it.
+            for instr in &mut c.code {
+                for arg in &mut instr.args {
+                    if let Arg::Label(ref mut j) = *arg {
+                        if *j > i {
+                            *j -= 1;
+                        }
+                    }
+                }
+            }
+            out.push(c);
+        }
+        if !self.inputs.is_empty() {
+            let mut c = self.clone();
+            c.inputs.pop();
+            out.push(c);
+        }
+        for i in 0..self.code.len() {
+            if self.code[i].immediate_write {
+                let mut c = self.clone();
+                c.code[i].immediate_write = false;
+                out.push(c);
+            }
+            for a in 0..self.code[i].args.len() {
+                let simpler = match self.code[i].args[a] {
+                    Arg::Imm(v) if v != 0 => vec![Arg::Imm(0), Arg::Imm(v / 2)],
+                    Arg::Far(k) => vec![Arg::Data(0), Arg::Far(k / 2)],
+                    Arg::Data(k) if k != 0 => vec![Arg::Data(0)],
+                    _ => vec![],
+                };
+                for arg in simpler {
+                    if arg != self.code[i].args[a] {
+                        let mut c = self.clone();
+                        c.code[i].args[a] = arg;
+                        out.push(c);
+                    }
+                }
+            }
+        }
+        let used = |k: usize| {
+            self.code
+                .iter()
+                .any(|instr| instr.args.contains(&Arg::Data(k)))
+        };
+        if !self.data.is_empty() && !used(self.data.len() - 1) {
+            let mut c = self.clone();
+            c.data.pop();
+            out.push(c);
+        }
+        for k in 0..self.data.len() {
+            let v = self.data[k];
+            for &smaller in &[0, v / 2, v - v.signum()] {
+                if smaller != v {
+                    let mut c = self.clone();
+                    c.data[k] = smaller;
+                    out.push(c);
+                }
+            }
+        }
+        out
+    }
+}
+
+fn agree(a: &Outcome, b: &Outcome) -> bool {
+    a.outputs == b.outputs && a.halted == b.halted && (!a.halted || a.memory == b.memory)
+}
+
+fn mismatch(case: &Case, runners: &mut [Runner]) -> Option<Mismatch> {
+    let program = case.program();
+    let outcomes: Vec<(String, Outcome)> = runners
+        .iter_mut()
+        .map(|&mut (name, ref mut run)| {
+            let mut outcome = run(&program, &case.inputs);
+            outcome.memory.truncate(program.len());
+            (name.to_string(), outcome)
+        })
+        .collect();
+    if outcomes.windows(2).all(|w| agree(&w[0].1, &w[1].1)) {
+        None
+    } else {
+        Some(Mismatch {
+            program,
+            inputs: case.inputs.clone(),
+            outcomes,
+        })
+    }
+}
+
+/// Runs `config.cases` random programs on every runner. Returns how many
+/// ran, or the first disagreement shrunk to a minimal program.
+pub fn fuzz(config: &FuzzConfig, runners: &mut [Runner]) -> Result<usize, Mismatch> {
+    let mut rng = Rng(config.seed.max(1));
+    for _ in 0..config.cases {
+        let mut case = Case::generate(&mut rng, config);
+        let mut found = match mismatch(&case, runners) {
+            Some(found) => found,
+            None => continue,
+        };
+        'shrinking: loop {
+            for smaller in case.shrinks() {
+                if let Some(m) = mismatch(&smaller, runners) {
+                    case = smaller;
+                    found = m;
+                    continue 'shrinking;
+                }
+            }
+            return Err(found);
+        }
+    }
+    Ok(config.cases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with(prog: &[i64], inputs: &[i64], cached: bool, hooked: bool) -> Outcome {
+        let mut machine = Machine::new(prog.to_vec());
+        machine.set_decode_cache(cached);
+        if hooked {
+            // Any hook takes every step through the checked interpreter
+            // rather than the plain loop.
+            machine.set_budget(Some(u64::MAX));
+        }
+        let mut outputs = Vec::new();
+        let status = machine.run(&mut IterInput(inputs.iter().cloned()), &mut outputs);
+        Outcome {
+            outputs,
+            memory: machine.memory().dense().to_vec(),
+            halted: status == Ok(RunStatus::Halted),
+        }
+    }
+
+    #[test]
+    fn generated_programs_terminate_and_agree() {
+        let config = FuzzConfig {
+            edge_cases: true,
+            ..FuzzConfig::default()
+        };
+        let mut halted = 0;
+        let res = fuzz(
+            &config,
+            &mut [
+                ("cached", &mut |p: &[i64], i: &[i64]| {
+                    let outcome = run_with(p, i, true, false);
+                    halted += outcome.halted as usize;
+                    outcome
+                }),
+                ("uncached", &mut |p: &[i64], i: &[i64]| {
+                    run_with(p, i, false, false)
+                }),
+                ("hooked", &mut |p: &[i64], i: &[i64]| {
+                    run_with(p, i, true, true)
+                }),
+            ],
+        );
+        assert_eq!(res, Ok(config.cases));
+        // Edge cases fault some programs but most still run to the end.
+        assert!(halted > config.cases / 2, "{}", halted);
+    }
+
+    #[test]
+    fn shrinks_to_the_difference() {
+        // An interpreter whose multiply adds instead.
+        let mut broken = |prog: &[i64], inputs: &[i64]| {
+            let patched: Vec<i64> = prog
+                .iter()
+                .enumerate()
+                .map(|(i, &w)| if i == 0 && w % 100 == 2 { w - 1 } else { w })
+                .collect();
+            let mut outcome = machine_outcome(&patched, inputs);
+            outcome.memory[0] = prog[0];
+            outcome
+        };
+        let config = FuzzConfig {
+            level: Level::Day2,
+            ..FuzzConfig::default()
+        };
+        let found = fuzz(
+            &config,
+            &mut [("machine", &mut machine_outcome), ("broken", &mut broken)],
+        )
+        .unwrap_err();
+        assert_eq!(found.program, vec![2, 5, 5, 5, 99, 1]);
+        assert_eq!(
+            found.to_string(),
+            "program [2, 5, 5, 5, 99, 1]\n\
+             inputs []\n\
+             machine: outputs [], halted, memory [2, 5, 5, 5, 99, 1]\n\
+             broken: outputs [], halted, memory [2, 5, 5, 5, 99, 2]\n"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Running a whole program in one go, for the days that only care about
+//! what it left behind once it halted.
+
+use error::VmError;
+use io::IterInput;
+use machine::{Machine, RunStatus};
+use std::error::Error;
+use std::fmt;
+
+/// Why a program didn't run through to its halt.
+#[derive(Clone, PartialEq, Debug)]
+pub enum HaltError {
+    Fault(VmError),
+    /// The program read more input than it was given.
+    NeedsInput {
+        pc: usize,
+    },
+    /// The program came back to an earlier state without halting.
+    InfiniteLoop {
+        pc: usize,
+    },
+    /// A breakpoint, watchpoint, budget or timeout set on the machine ended
+    /// the run first.
+    Stopped {
+        status: RunStatus,
+        pc: usize,
+    },
+}
+
+impl fmt::Display for HaltError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HaltError::Fault(ref e) => write!(f, "{}", e),
+            HaltError::NeedsInput { pc } => write!(f, "out of input at pc {}", pc),
+            HaltError::InfiniteLoop { pc } => write!(f, "infinite loop at pc {}", pc),
+            HaltError::Stopped { status, pc } => write!(f, "stopped by {:?} at pc {}", status, pc),
+        }
+    }
+}
+
+impl Error for HaltError {}
+
+/// Runs the machine with loop detection on until it halts, reading only
+/// `inputs`, and returns its outputs and final dense memory.
+pub fn run_to_halt(
+    mut machine: Machine,
+    inputs: &[i64],
+) -> Result<(Vec<i64>, Vec<i64>), HaltError> {
+    machine.set_loop_detection(true);
+    let mut outputs = Vec::new();
+    let status = machine
+        .run(&mut IterInput(inputs.iter().cloned()), &mut outputs)
+        .map_err(HaltError::Fault)?;
+    let pc = machine.state().prog_count;
+    match status {
+        RunStatus::Halted => Ok((outputs, machine.memory().dense().to_vec())),
+        RunStatus::NeedsInput => Err(HaltError::NeedsInput { pc }),
+        RunStatus::LoopDetected(pc) => Err(HaltError::InfiniteLoop { pc }),
+        status => Err(HaltError::Stopped { status, pc }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_why_it_stopped() {
+        let run = |prog: &[i64], inputs: &[i64]| run_to_halt(Machine::new(prog.to_vec()), inputs);
+        assert_eq!(
+            run(&[3, 0, 4, 0, 99], &[7]),
+            Ok((vec![7], vec![7, 0, 4, 0, 99]))
+        );
+        assert_eq!(
+            run(&[3, 0, 3, 0, 99], &[1]),
+            Err(HaltError::NeedsInput { pc: 2 })
+        );
+        assert_eq!(
+            run(&[1105, 1, 0], &[]),
+            Err(HaltError::InfiniteLoop { pc: 0 })
+        );
+        assert_eq!(
+            run(&[11101, 2, 3, 7, 99], &[]),
+            Err(HaltError::Fault(VmError::ImmediateWrite {
+                pc: 0,
+                instr: 11101
+            }))
+        );
+
+        let mut machine = Machine::new(vec![1101, 2, 3, 7, 99]);
+        machine.set_budget(Some(0));
+        assert_eq!(
+            run_to_halt(machine, &[]),
+            Err(HaltError::Stopped {
+                status: RunStatus::BudgetExhausted,
+                pc: 0
+            })
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use error::VmError;
+use machine::ProcState;
+use memory::Memory;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Opcode {
+    Add = 1,
+    Multiply = 2,
+    Input = 3,
+    Output = 4,
+    JumpTrue = 5,
+    JumpFalse = 6,
+    LessThan = 7,
+    Equals = 8,
+    AdjustRelBase = 9,
+    Halt = 99,
+}
+
+impl Opcode {
+    pub fn from_i64(value: i64) -> Result<Opcode, String> {
+        Opcode::from_code(value).ok_or_else(|| format!("Invalid Opcode: {}", value))
+    }
+
+    /// Like `from_i64` but without an error message to build, for decoding
+    /// in the interpreter's inner loop.
+    pub fn from_code(value: i64) -> Option<Opcode> {
+        match value {
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Multiply),
+            3 => Some(Opcode::Input),
+            4 => Some(Opcode::Output),
+            5 => Some(Opcode::JumpTrue),
+            6 => Some(Opcode::JumpFalse),
+            7 => Some(Opcode::LessThan),
+            8 => Some(Opcode::Equals),
+            9 => Some(Opcode::AdjustRelBase),
+            99 => Some(Opcode::Halt),
+            _ => None,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Multiply => "mul",
+            Opcode::Input => "in",
+            Opcode::Output => "out",
+            Opcode::JumpTrue => "jt",
+            Opcode::JumpFalse => "jf",
+            Opcode::LessThan => "lt",
+            Opcode::Equals => "eq",
+            Opcode::AdjustRelBase => "arb",
+            Opcode::Halt => "hlt",
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        match mnemonic {
+            "add" => Some(Opcode::Add),
+            "mul" => Some(Opcode::Multiply),
+            "in" => Some(Opcode::Input),
+            "out" => Some(Opcode::Output),
+            "jt" => Some(Opcode::JumpTrue),
+            "jf" => Some(Opcode::JumpFalse),
+            "lt" => Some(Opcode::LessThan),
+            "eq" => Some(Opcode::Equals),
+            "arb" => Some(Opcode::AdjustRelBase),
+            "hlt" => Some(Opcode::Halt),
+            _ => None,
+        }
+    }
+
+    pub fn operand_count(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => 3,
+            Opcode::JumpTrue | Opcode::JumpFalse => 2,
+            Opcode::Input | Opcode::Output | Opcode::AdjustRelBase => 1,
+            Opcode::Halt => 0,
+        }
+    }
+
+    /// Whether the last operand is a write target rather than a value.
+    pub fn writes_last(self) -> bool {
+        matches!(
+            self,
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals | Opcode::Input
+        )
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OperandMode {
+    Position = 0,
+    Immediate = 1,
+    Relative = 2,
+}
+
+impl OperandMode {
+    pub fn from_i64(value: i64) -> Result<OperandMode, String> {
+        OperandMode::from_digit(value).ok_or_else(|| format!("Invalid OperandMode: {}", value))
+    }
+
+    /// Like `from_i64` but without an error message to build.
+    pub fn from_digit(value: i64) -> Option<OperandMode> {
+        match value {
+            0 => Some(OperandMode::Position),
+            1 => Some(OperandMode::Immediate),
+            2 => Some(OperandMode::Relative),
+            _ => None,
+        }
+    }
+}
+
+pub fn operand_modes(x: i64) -> Result<[OperandMode; 3], String> {
+    mode_digits(x).ok_or_else(|| format!("Invalid OperandMode: {}", x))
+}
+
+fn mode_digits(x: i64) -> Option<[OperandMode; 3]> {
+    if !(0..1000).contains(&x) {
+        return None;
+    }
+    Some([
+        OperandMode::from_digit(x % 10)?,
+        OperandMode::from_digit(x / 10 % 10)?,
+        OperandMode::from_digit(x / 100)?,
+    ])
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ParsedInstruction {
+    Add { op1: i64, op2: i64, dest: usize },
+    Multiply { op1: i64, op2: i64, dest: usize },
+    Input { dest: usize },
+    Output { out: i64 },
+    JumpTrue { test: i64, jump_dest: usize },
+    JumpFalse { test: i64, jump_dest: usize },
+    LessThan { op1: i64, op2: i64, dest: usize },
+    Equals { op1: i64, op2: i64, dest: usize },
+    AdjustRelBase { adj: i64 },
+    Halt,
+}
+
+/// An instruction split into its parts, with operands not yet resolved
+/// against memory or the relative base.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RawInstr {
+    pub op: Opcode,
+    pub modes: [OperandMode; 3],
+    /// The undecoded opcode word, for error reporting.
+    pub word: i64,
+    /// Operand words; only the first `op.operand_count()` are meaningful.
+    pub operands: [i64; 3],
+}
+
+/// Resolves the operands of a fetched instruction and advances the program
+/// counter past it. On error the program counter is left unchanged.
+pub fn resolve_instr(
+    ps: &mut ProcState,
+    mem: &Memory,
+    raw: &RawInstr,
+) -> Result<ParsedInstruction, VmError> {
+    let pc = ps.prog_count;
+    let instr = raw.word;
+    let load = |a: usize| -> Result<i64, VmError> {
+        mem.get(a).map_err(|e| VmError::AddressTooLarge {
+            pc,
+            instr,
+            addr: e.addr,
+        })
+    };
+    let addr = |a: i64| -> Result<usize, VmError> {
+        if a < 0 {
+            Err(VmError::NegativeAddress { pc, instr, addr: a })
+        } else {
+            Ok(a as usize)
+        }
+    };
+    let rel_base = ps.rel_base;
+    let relative = |offset: i64| -> Result<usize, VmError> {
+        match rel_base.checked_add(offset) {
+            Some(a) => addr(a),
+            None => Err(VmError::ArithmeticOverflow { pc, instr }),
+        }
+    };
+    let parse_operand = |i: usize, read| -> Result<i64, VmError> {
+        let raw_opand_val = raw.operands[i];
+        Ok(if read {
+            match raw.modes[i] {
+                OperandMode::Position => load(addr(raw_opand_val)?)?,
+                OperandMode::Immediate => raw_opand_val,
+                OperandMode::Relative => load(relative(raw_opand_val)?)?,
+            }
+        } else {
+            match raw.modes[i] {
+                OperandMode::Position => addr(raw_opand_val)? as i64,
+                OperandMode::Immediate => return Err(VmError::ImmediateWrite { pc, instr }),
+                OperandMode::Relative => relative(raw_opand_val)? as i64,
+            }
+        })
+    };
+
+    let parsed = match raw.op {
+        Opcode::Add => ParsedInstruction::Add {
+            op1: parse_operand(0, true)?,
+            op2: parse_operand(1, true)?,
+            dest: parse_operand(2, false)? as usize,
+        },
+        Opcode::Multiply => ParsedInstruction::Multiply {
+            op1: parse_operand(0, true)?,
+            op2: parse_operand(1, true)?,
+            dest: parse_operand(2, false)? as usize,
+        },
+        Opcode::Input => ParsedInstruction::Input {
+            dest: parse_operand(0, false)? as usize,
+        },
+        Opcode::Output => ParsedInstruction::Output {
+            out: parse_operand(0, true)?,
+        },
+        Opcode::JumpTrue => ParsedInstruction::JumpTrue {
+            test: parse_operand(0, true)?,
+            jump_dest: addr(parse_operand(1, true)?)?,
+        },
+        Opcode::JumpFalse => ParsedInstruction::JumpFalse {
+            test: parse_operand(0, true)?,
+            jump_dest: addr(parse_operand(1, true)?)?,
+        },
+        Opcode::LessThan => ParsedInstruction::LessThan {
+            op1: parse_operand(0, true)?,
+            op2: parse_operand(1, true)?,
+            dest: parse_operand(2, false)? as usize,
+        },
+        Opcode::Equals => ParsedInstruction::Equals {
+            op1: parse_operand(0, true)?,
+            op2: parse_operand(1, true)?,
+            dest: parse_operand(2, false)? as usize,
+        },
+        Opcode::AdjustRelBase => ParsedInstruction::AdjustRelBase {
+            adj: parse_operand(0, true)?,
+        },
+        Opcode::Halt => ParsedInstruction::Halt,
+    };
+    ps.prog_count += 1 + raw.op.operand_count();
+    Ok(parsed)
+}
+
+/// Splits an opcode word into its opcode and operand modes, or returns
+/// `None` if it isn't a valid one.
+pub fn split_word(word: i64) -> Option<(Opcode, [OperandMode; 3])> {
+    Some((Opcode::from_code(word % 100)?, mode_digits(word / 100)?))
+}
+
+/// Like `split_word` but says what's wrong with the opcode word `word`,
+/// found at `pc`.
+pub fn decode_word(pc: usize, word: i64) -> Result<(Opcode, [OperandMode; 3]), VmError> {
+    split_word(word).ok_or_else(|| match Opcode::from_code(word % 100) {
+        None => VmError::InvalidOpcode { pc, instr: word },
+        Some(_) => VmError::InvalidOperandMode { pc, instr: word },
+    })
+}
+
+/// Reads the instruction at `pc`: splits the opcode word into opcode and
+/// operand modes and collects the operand words after it.
+pub fn fetch_instr(pc: usize, mem: &Memory) -> Result<RawInstr, VmError> {
+    fetch_instr_with(pc, mem, decode_word)
+}
+
+/// Like `fetch_instr` but has `decode` split the opcode word.
+pub fn fetch_instr_with<D>(pc: usize, mem: &Memory, decode: D) -> Result<RawInstr, VmError>
+where
+    D: FnOnce(usize, i64) -> Result<(Opcode, [OperandMode; 3]), VmError>,
+{
+    let combined_opcode = mem.get(pc).map_err(|e| VmError::AddressTooLarge {
+        pc,
+        instr: 0,
+        addr: e.addr,
+    })?;
+    let (opcode, operand_modes) = decode(pc, combined_opcode)?;
+    let mut operands = [0; 3];
+    for (i, operand) in operands.iter_mut().take(opcode.operand_count()).enumerate() {
+        *operand = mem.get(pc + 1 + i).map_err(|e| VmError::AddressTooLarge {
+            pc,
+            instr: combined_opcode,
+            addr: e.addr,
+        })?;
+    }
+    Ok(RawInstr {
+        op: opcode,
+        modes: operand_modes,
+        word: combined_opcode,
+        operands,
+    })
+}
+
+/// Decodes the instruction at `ps.prog_count`, resolving its operands and
+/// advancing the program counter past it.
+pub fn parse_next_instr(ps: &mut ProcState, mem: &Memory) -> Result<ParsedInstruction, VmError> {
+    let raw = fetch_instr(ps.prog_count, mem)?;
+    resolve_instr(ps, mem, &raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_modes() {
+        assert_eq!(
+            operand_modes(1002 / 100).unwrap(),
+            [
+                OperandMode::Position,
+                OperandMode::Immediate,
+                OperandMode::Position
+            ]
+        );
+        assert_eq!(
+            operand_modes(21201 / 100).unwrap(),
+            [
+                OperandMode::Relative,
+                OperandMode::Immediate,
+                OperandMode::Relative
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_modes() {
+        assert!(operand_modes(3).is_err());
+        assert!(operand_modes(1000).is_err());
+    }
+
+    fn decode(prog: Vec<i64>) -> Result<ParsedInstruction, VmError> {
+        parse_next_instr(&mut ProcState::default(), &Memory::new(prog))
+    }
+
+    #[test]
+    fn faults_carry_pc_and_word() {
+        assert_eq!(
+            decode(vec![42]).unwrap_err(),
+            VmError::InvalidOpcode { pc: 0, instr: 42 }
+        );
+        assert_eq!(
+            decode(vec![301, 0, 0, 0]).unwrap_err(),
+            VmError::InvalidOperandMode { pc: 0, instr: 301 }
+        );
+        assert_eq!(
+            decode(vec![11101, 1, 1, 0]).unwrap_err(),
+            VmError::ImmediateWrite {
+                pc: 0,
+                instr: 11101
+            }
+        );
+        assert_eq!(
+            decode(vec![1, -1, 0, 0]).unwrap_err(),
+            VmError::NegativeAddress {
+                pc: 0,
+                instr: 1,
+                addr: -1
+            }
+        );
+        assert_eq!(
+            decode(vec![1105, 1, -7]).unwrap_err(),
+            VmError::NegativeAddress {
+                pc: 0,
+                instr: 1105,
+                addr: -7
+            }
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, StdinLock};
+use std::sync::mpsc::{Receiver, Sender};
+
+/// A source of values for the Intcode input instruction.
+pub trait Input {
+    /// Returns the next value, `Ok(None)` if none is available yet, or the
+    /// offending text if the source couldn't turn it into a number.
+    fn read(&mut self) -> Result<Option<i64>, String>;
+}
+
+/// A sink for values produced by the Intcode output instruction.
+pub trait Output {
+    fn write(&mut self, value: i64);
+}
+
+impl<F> Input for F
+where
+    F: FnMut() -> Option<i64>,
+{
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        Ok(self())
+    }
+}
+
+impl<F> Output for F
+where
+    F: FnMut(i64),
+{
+    fn write(&mut self, value: i64) {
+        self(value)
+    }
+}
+
+impl Input for VecDeque<i64> {
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        Ok(self.pop_front())
+    }
+}
+
+impl Output for VecDeque<i64> {
+    fn write(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+impl Output for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+/// Feeds the values of an iterator, e.g. `IterInput(vec![1, 2].into_iter())`.
+pub struct IterInput<I>(pub I);
+
+impl<I> Input for IterInput<I>
+where
+    I: Iterator<Item = i64>,
+{
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        Ok(self.0.next())
+    }
+}
+
+/// Blocks until a value arrives. A disconnected channel reads as no input.
+impl Input for Receiver<i64> {
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        Ok(self.recv().ok())
+    }
+}
+
+/// Values sent after the receiving end hung up are dropped.
+impl Output for Sender<i64> {
+    fn write(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+/// Reads one number per line. This is the only input that deals in text;
+/// blank lines are skipped and end of input reads as no input.
+pub struct LineInput<R> {
+    reader: R,
+}
+
+impl<R: BufRead> LineInput<R> {
+    pub fn new(reader: R) -> LineInput<R> {
+        LineInput { reader }
+    }
+}
+
+impl LineInput<StdinLock<'static>> {
+    pub fn stdin() -> LineInput<StdinLock<'static>> {
+        LineInput::new(io::stdin().lock())
+    }
+}
+
+impl<R: BufRead> Input for LineInput<R> {
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        loop {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return Ok(None),
+                Ok(_) => (),
+            }
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            return trimmed
+                .parse::<i64>()
+                .map(Some)
+                .map_err(|_| trimmed.to_string());
+        }
+    }
+}
+
+/// Prints each output on its own line.
+pub struct StdoutOutput;
+
+impl Output for StdoutOutput {
+    fn write(&mut self, value: i64) {
+        println!("OUT-> {}", value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn line_input_parses_lines() {
+        let mut input = LineInput::new("1\n\n -2 \nabc\n".as_bytes());
+        assert_eq!(input.read(), Ok(Some(1)));
+        assert_eq!(input.read(), Ok(Some(-2)));
+        assert_eq!(input.read(), Err("abc".to_string()));
+        assert_eq!(input.read(), Ok(None));
+    }
+
+    #[test]
+    fn channel_closes() {
+        let (tx, mut rx) = channel();
+        let mut tx: Sender<i64> = tx;
+        tx.write(5);
+        drop(tx);
+        assert_eq!(rx.read(), Ok(Some(5)));
+        assert_eq!(rx.read(), Ok(None));
+    }
+}
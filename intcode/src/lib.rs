@@ -0,0 +1,52 @@
+//! Shared Intcode computer used by the 2019 puzzles from day 5 onwards.
+
+mod arith;
+mod ascii;
+mod asm;
+mod cache;
+mod cfg;
+mod conformance;
+mod debugger;
+mod disasm;
+mod error;
+mod fuzz;
+mod halt;
+mod instr;
+mod io;
+mod machine;
+mod memory;
+mod network;
+mod profile;
+mod program;
+mod snapshot;
+mod trace;
+mod varint;
+mod watchdog;
+
+pub use arith::Arithmetic;
+pub use ascii::{ascii_values, AsciiMachine, AsciiOutput};
+pub use asm::{assemble, AsmError};
+pub use cfg::{build_cfg, Block, Cfg, EdgeKind, Exit, Function, Loop};
+pub use conformance::{check_conformance, conformance_cases, ConformanceCase, Level};
+pub use debugger::Debugger;
+pub use disasm::{
+    decode_at, disassemble, format_listing, label_name, listing, Decoded, DisasmLine, Operand,
+};
+pub use error::VmError;
+pub use fuzz::{fuzz, machine_outcome, FuzzConfig, Mismatch, Outcome, Runner};
+pub use halt::{run_to_halt, HaltError};
+pub use instr::{
+    fetch_instr, operand_modes, parse_next_instr, resolve_instr, Opcode, OperandMode,
+    ParsedInstruction, RawInstr,
+};
+pub use io::{Input, IterInput, LineInput, Output, StdoutOutput};
+pub use machine::{Machine, ProcState, RunStatus};
+pub use memory::{Memory, MemoryLimit, DEFAULT_DENSE_LIMIT, DEFAULT_MAX_SIZE};
+pub use network::{Blocked, Network, NetworkError, NetworkStatus};
+pub use profile::{HotLoop, Profile};
+pub use program::{
+    apply_patches, describe_patches, load_patches, load_program, parse_patches, parse_program,
+    take_limit_args, take_patch_args, Limits, Patch,
+};
+pub use snapshot::Snapshot;
+pub use trace::{first_divergence, Trace, TraceRecord};
@@ -0,0 +1,1023 @@
+use arith::Arithmetic;
+use cache::DecodeCache;
+use error::VmError;
+use instr::{fetch_instr, resolve_instr, split_word, Opcode, OperandMode, ParsedInstruction};
+use io::{Input, Output};
+use memory::{Memory, MemoryLimit};
+use profile::Profile;
+use snapshot::Snapshot;
+use std::collections::{BTreeSet, VecDeque};
+use std::mem;
+use std::time::{Duration, Instant};
+use trace::{Trace, TraceRecord};
+use watchdog::LoopDetector;
+
+/// How many instructions run between readings of the clock when a timeout
+/// is set.
+const CLOCK_INTERVAL: u32 = 4096;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct ProcState {
+    pub prog_count: usize,
+    pub rel_base: i64,
+}
+
+/// Why a run returned control to the caller.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RunStatus {
+    /// The program executed a halt; the program counter stays on it.
+    Halted,
+    /// The program wants input nobody has provided yet. The input
+    /// instruction has not executed and will be retried on the next run.
+    NeedsInput,
+    Output(i64),
+    /// Execution reached a breakpoint address; the instruction there has
+    /// not executed yet.
+    BreakpointHit(usize),
+    /// The instruction that just executed wrote to a watched address.
+    WatchpointHit {
+        addr: usize,
+        old: i64,
+        new: i64,
+    },
+    BudgetExhausted,
+    /// The deadline set with `set_timeout` passed.
+    TimedOut,
+    /// The machine came back to an earlier state, at this address, without
+    /// any input or output in between, so it would loop forever.
+    LoopDetected(usize),
+}
+
+/// An Intcode computer: program memory plus the processor state needed to
+/// resume it after it blocks on input.
+#[derive(Clone, Debug)]
+pub struct Machine {
+    mem: Memory,
+    state: ProcState,
+    inputs: VecDeque<i64>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    budget: Option<u64>,
+    trace: Option<Trace>,
+    profile: Option<Profile>,
+    arithmetic: Arithmetic,
+    cache: Option<DecodeCache>,
+    deadline: Option<Instant>,
+    /// Instructions left before the clock is read again.
+    ticks: u32,
+    loops: Option<LoopDetector>,
+}
+
+impl Machine {
+    pub fn new(prog: Vec<i64>) -> Machine {
+        Machine::with_memory(Memory::new(prog))
+    }
+
+    pub fn with_memory(mem: Memory) -> Machine {
+        Machine {
+            cache: Some(DecodeCache::new(mem.dense().len())),
+            mem,
+            state: ProcState::default(),
+            inputs: VecDeque::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            budget: None,
+            trace: None,
+            profile: None,
+            arithmetic: Arithmetic::default(),
+            deadline: None,
+            ticks: 0,
+            loops: None,
+        }
+    }
+
+    /// Rebuilds a machine from a snapshot. Breakpoints, watchpoints, the
+    /// budget and tracing start out cleared. Sparse words beyond the
+    /// snapshot's memory limit are dropped; `Snapshot::from_bytes` already
+    /// refuses files that have any.
+    pub fn restore(snapshot: Snapshot) -> Machine {
+        let mut mem = Memory::new(snapshot.memory);
+        mem.set_max_size(snapshot.max_size);
+        for (addr, value) in snapshot.sparse {
+            if let Ok(word) = mem.get_mut(addr) {
+                *word = value;
+            }
+        }
+        let mut machine = Machine::with_memory(mem);
+        machine.state = snapshot.state;
+        machine.inputs = snapshot.inputs.into_iter().collect();
+        machine
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.mem.dense().to_vec(),
+            sparse: self.mem.sparse_words(),
+            max_size: self.mem.max_size(),
+            state: self.state,
+            inputs: self.inputs.iter().cloned().collect(),
+        }
+    }
+
+    pub fn state(&self) -> ProcState {
+        self.state
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.mem
+    }
+
+    /// Direct access to memory. Anything could be rewritten through it, so
+    /// this drops every cached decode and makes loop detection rehash
+    /// memory.
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        if let Some(ref mut cache) = self.cache {
+            cache.clear();
+        }
+        if let Some(ref mut loops) = self.loops {
+            loops.invalidate();
+        }
+        &mut self.mem
+    }
+
+    pub fn read(&self, addr: usize) -> Result<i64, MemoryLimit> {
+        self.mem.get(addr)
+    }
+
+    pub fn write(&mut self, addr: usize, value: i64) -> Result<(), MemoryLimit> {
+        let old = mem::replace(self.mem.get_mut(addr)?, value);
+        if let Some(ref mut cache) = self.cache {
+            cache.invalidate(addr);
+        }
+        if let Some(ref mut loops) = self.loops {
+            loops.write(addr, old, value);
+        }
+        Ok(())
+    }
+
+    /// Queues a value for the next input instructions run by `step`,
+    /// `run_until_output` and `run_until_input`.
+    pub fn push_input(&mut self, value: i64) {
+        self.inputs.push_back(value);
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, addr: usize) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.remove(&addr)
+    }
+
+    pub fn watchpoints(&self) -> &BTreeSet<usize> {
+        &self.watchpoints
+    }
+
+    /// Limits how many more instructions may execute before runs return
+    /// `BudgetExhausted`. `None` removes the limit.
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+    }
+
+    pub fn budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    /// Makes runs return `TimedOut` once `timeout` has passed from now.
+    /// `None` removes the limit. The clock is only read every few thousand
+    /// instructions, so runs may overshoot by a little.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|t| Instant::now() + t);
+        self.ticks = 0;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Turns on or off detection of infinite loops: runs return
+    /// `LoopDetected` when the machine repeats a state exactly without any
+    /// I/O in between. Off by default since it slows every instruction a
+    /// little.
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loops = if enabled {
+            Some(LoopDetector::new())
+        } else {
+            None
+        };
+    }
+
+    /// Chooses how Add and Multiply handle overflow. Defaults to
+    /// `Arithmetic::Checked`.
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
+    /// Turns the decoded instruction cache on or off. It's on by default;
+    /// turning it off decodes every instruction from scratch. Turning it on
+    /// when it already is keeps what it has cached.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        if !enabled {
+            self.cache = None;
+        } else if self.cache.is_none() {
+            self.cache = Some(DecodeCache::new(self.mem.dense().len()));
+        }
+    }
+
+    /// Starts recording every executed instruction, discarding any trace
+    /// recorded so far.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace::new());
+    }
+
+    /// Stops recording and returns the trace, if one was started.
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// Starts counting executed instructions, memory traffic and I/O,
+    /// discarding any profile gathered so far.
+    pub fn start_profile(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Stops profiling and returns the profile, if one was started.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Executes a single instruction, reading from the queued input.
+    /// Returns the status if the instruction produced one (output, halt,
+    /// watched write or blocked on input) and `None` otherwise.
+    /// Breakpoints and the budget are ignored.
+    pub fn step(&mut self) -> Result<Option<RunStatus>, VmError> {
+        self.step_with(&mut || None)
+    }
+
+    /// Like `step` but reads from `input` once the queued input runs dry.
+    pub fn step_with<I>(&mut self, input: &mut I) -> Result<Option<RunStatus>, VmError>
+    where
+        I: Input,
+    {
+        let mut inputs = mem::take(&mut self.inputs);
+        let res = if inputs.is_empty() {
+            self.exec_one(input)
+        } else {
+            self.exec_one(&mut inputs)
+        };
+        self.inputs = inputs;
+        res
+    }
+
+    /// Runs on the queued input until the next output value, or until the
+    /// program halts, blocks, hits a breakpoint or watchpoint or runs out
+    /// of budget.
+    pub fn run_until_output(&mut self) -> Result<RunStatus, VmError> {
+        let plain = !self.hooked();
+        let mut first = true;
+        loop {
+            if plain {
+                if let Some(status) = self.run_plain() {
+                    return Ok(status);
+                }
+            }
+            if let Some(status) = self.check_stop(first) {
+                return Ok(status);
+            }
+            first = false;
+            let res = self.step();
+            self.charge(&res);
+            if let Some(status) = res? {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Runs on the queued input, handing every output to `output`, until
+    /// the program halts, needs more input, hits a breakpoint or watchpoint
+    /// or runs out of budget.
+    pub fn run_until_input<O>(&mut self, output: &mut O) -> Result<RunStatus, VmError>
+    where
+        O: Output,
+    {
+        let plain = !self.hooked();
+        loop {
+            // Short lived machines, like day 7's amplifiers, spend much of
+            // their time getting in and out of runs, so outputs are taken
+            // straight from the plain loop where possible.
+            let next = if plain { self.run_plain() } else { None };
+            let status = match next {
+                Some(status) => status,
+                None => self.run_until_output()?,
+            };
+            match status {
+                RunStatus::Output(out) => output.write(out),
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Like `run_until_input` but reads from `input` once the queued input
+    /// runs dry.
+    pub fn run<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<RunStatus, VmError>
+    where
+        I: Input,
+        O: Output,
+    {
+        let plain = !self.hooked();
+        let mut first = true;
+        loop {
+            if plain {
+                while let Some(status) = self.run_plain() {
+                    match status {
+                        RunStatus::Output(out) => output.write(out),
+                        // Left for `exec_one` to read from `input`.
+                        RunStatus::NeedsInput => break,
+                        status => return Ok(status),
+                    }
+                }
+            }
+            if let Some(status) = self.check_stop(first) {
+                return Ok(status);
+            }
+            first = false;
+            let res = self.step_with(input);
+            self.charge(&res);
+            match res? {
+                Some(RunStatus::Output(out)) => output.write(out),
+                Some(status) => return Ok(status),
+                None => (),
+            }
+        }
+    }
+
+    /// Whether anything is set that has to see every instruction:
+    /// breakpoints, watchpoints, a budget or timeout, a trace, a profile or
+    /// loop detection.
+    fn hooked(&self) -> bool {
+        !self.breakpoints.is_empty()
+            || !self.watchpoints.is_empty()
+            || self.budget.is_some()
+            || self.deadline.is_some()
+            || self.trace.is_some()
+            || self.profile.is_some()
+            || self.loops.is_some()
+    }
+
+    /// Runs instructions straight on the dense memory and the queued
+    /// input, without any of the bookkeeping in `exec_one`, until one
+    /// outputs, halts or runs out of queued input, and returns that status.
+    /// Returns `None` at the first instruction that might fault or reach
+    /// memory beyond the dense words, leaving it for `exec_one`. Only for
+    /// runs with nothing `hooked`.
+    fn run_plain(&mut self) -> Option<RunStatus> {
+        let arithmetic = self.arithmetic;
+        let mem = self.mem.dense_within_limit_mut();
+        let (cache, inputs) = (&mut self.cache, &mut self.inputs);
+        while let Some((next, status)) = plain_step(mem, cache, inputs, arithmetic, self.state) {
+            self.state = next;
+            if status.is_some() {
+                return status;
+            }
+        }
+        None
+    }
+
+    /// Checks the breakpoints, budget and deadline before the next
+    /// instruction. A breakpoint at the address a run starts from is not
+    /// reported again, so resuming after a hit makes progress.
+    fn check_stop(&mut self, first: bool) -> Option<RunStatus> {
+        let pc = self.state.prog_count;
+        if !first && self.breakpoints.contains(&pc) {
+            return Some(RunStatus::BreakpointHit(pc));
+        }
+        if self.budget == Some(0) {
+            return Some(RunStatus::BudgetExhausted);
+        }
+        if let Some(deadline) = self.deadline {
+            if self.ticks == 0 {
+                if Instant::now() >= deadline {
+                    return Some(RunStatus::TimedOut);
+                }
+                self.ticks = CLOCK_INTERVAL;
+            }
+            self.ticks -= 1;
+        }
+        None
+    }
+
+    /// Takes a step that ran from the budget. Faults and blocked inputs
+    /// leave the instruction to be retried, so they cost nothing.
+    fn charge(&mut self, res: &Result<Option<RunStatus>, VmError>) {
+        match *res {
+            Err(_) | Ok(Some(RunStatus::NeedsInput)) => (),
+            Ok(_) => {
+                if let Some(ref mut budget) = self.budget {
+                    *budget = budget.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    /// Undoes a partly executed instruction so the fault can be inspected
+    /// and the instruction retried.
+    fn fault(&mut self, at: ProcState, err: VmError) -> Result<Option<RunStatus>, VmError> {
+        self.state = at;
+        Err(err)
+    }
+
+    /// Executes one instruction. On a fault or a blocked input the program
+    /// counter is left on the instruction.
+    fn exec_one<I>(&mut self, input: &mut I) -> Result<Option<RunStatus>, VmError>
+    where
+        I: Input,
+    {
+        let mem = &mut self.mem;
+        let pc = &mut self.state;
+        let cached_pc = *pc;
+        let raw = match self.cache {
+            Some(ref mut cache) => cache.fetch(pc.prog_count, mem),
+            None => fetch_instr(pc.prog_count, mem),
+        };
+        let (raw, instr) = match raw.and_then(|raw| Ok((raw, resolve_instr(pc, mem, &raw)?))) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                *pc = cached_pc;
+                return Err(e);
+            }
+        };
+        let word = raw.word;
+        let arithmetic = self.arithmetic;
+        let overflow = VmError::ArithmeticOverflow {
+            pc: cached_pc.prog_count,
+            instr: word,
+        };
+
+        let mut input_value = None;
+        let mut status = None;
+        let write = match instr {
+            ParsedInstruction::Add { op1, op2, dest } => match arithmetic.add(op1, op2) {
+                Some(sum) => Some((dest, sum)),
+                None => return self.fault(cached_pc, overflow),
+            },
+            ParsedInstruction::Multiply { op1, op2, dest } => match arithmetic.mul(op1, op2) {
+                Some(product) => Some((dest, product)),
+                None => return self.fault(cached_pc, overflow),
+            },
+            // The destination is checked first so a fault doesn't lose the
+            // value; the retried instruction reads it again.
+            ParsedInstruction::Input { dest } => match mem.get(dest).map(|_| input.read()) {
+                Err(e) => {
+                    let err = VmError::AddressTooLarge {
+                        pc: cached_pc.prog_count,
+                        instr: word,
+                        addr: e.addr,
+                    };
+                    return self.fault(cached_pc, err);
+                }
+                Ok(Ok(Some(value))) => {
+                    input_value = Some(value);
+                    Some((dest, value))
+                }
+                Ok(Ok(None)) => {
+                    *pc = cached_pc;
+                    return Ok(Some(RunStatus::NeedsInput));
+                }
+                Ok(Err(line)) => {
+                    *pc = cached_pc;
+                    return Err(VmError::UnparsableInput {
+                        pc: cached_pc.prog_count,
+                        instr: word,
+                        input: line,
+                    });
+                }
+            },
+            ParsedInstruction::Output { out } => {
+                status = Some(RunStatus::Output(out));
+                None
+            }
+            ParsedInstruction::JumpTrue { test, jump_dest } => {
+                if test != 0 {
+                    pc.prog_count = jump_dest;
+                }
+                None
+            }
+            ParsedInstruction::JumpFalse { test, jump_dest } => {
+                if test == 0 {
+                    pc.prog_count = jump_dest;
+                }
+                None
+            }
+            ParsedInstruction::LessThan { op1, op2, dest } => {
+                Some((dest, if op1 < op2 { 1 } else { 0 }))
+            }
+
+            ParsedInstruction::Equals { op1, op2, dest } => {
+                Some((dest, if op1 == op2 { 1 } else { 0 }))
+            }
+
+            ParsedInstruction::AdjustRelBase { adj } => match pc.rel_base.checked_add(adj) {
+                Some(rel_base) => {
+                    pc.rel_base = rel_base;
+                    None
+                }
+                None => return self.fault(cached_pc, overflow),
+            },
+
+            ParsedInstruction::Halt => {
+                *pc = cached_pc;
+                status = Some(RunStatus::Halted);
+                None
+            }
+        };
+
+        let old = match write {
+            Some((addr, new)) => match mem.get_mut(addr) {
+                Ok(slot) => {
+                    if let Some(ref mut cache) = self.cache {
+                        cache.invalidate(addr);
+                    }
+                    let old = mem::replace(slot, new);
+                    if let Some(ref mut loops) = self.loops {
+                        loops.write(addr, old, new);
+                    }
+                    Some(old)
+                }
+                Err(e) => {
+                    let err = VmError::AddressTooLarge {
+                        pc: cached_pc.prog_count,
+                        instr: word,
+                        addr: e.addr,
+                    };
+                    return self.fault(cached_pc, err);
+                }
+            },
+            None => None,
+        };
+
+        if let Some(ref mut trace) = self.trace {
+            trace.push(&TraceRecord {
+                pc: cached_pc.prog_count,
+                rel_base: cached_pc.rel_base,
+                instr,
+                write,
+                input: input_value,
+                output: match status {
+                    Some(RunStatus::Output(out)) => Some(out),
+                    _ => None,
+                },
+            });
+        }
+
+        if let Some(ref mut profile) = self.profile {
+            profile.record(
+                cached_pc.prog_count,
+                cached_pc.rel_base,
+                &raw,
+                write.map(|(addr, _)| addr),
+                self.state.prog_count,
+            );
+        }
+
+        if let (Some((addr, new)), Some(old)) = (write, old) {
+            if self.watchpoints.contains(&addr) {
+                return Ok(Some(RunStatus::WatchpointHit { addr, old, new }));
+            }
+        }
+
+        if let Some(ref mut loops) = self.loops {
+            if status.is_some() || input_value.is_some() {
+                loops.reset();
+            } else if loops.observe(self.state.prog_count, self.state.rel_base, &self.mem) {
+                return Ok(Some(RunStatus::LoopDetected(self.state.prog_count)));
+            }
+        }
+
+        Ok(status)
+    }
+}
+
+/// Executes the instruction at `state` for `Machine::run_plain` and returns
+/// the state after it and any status it produced, or `None`, with nothing
+/// changed, if it has to go through `exec_one`.
+fn plain_step(
+    mem: &mut [i64],
+    cache: &mut Option<DecodeCache>,
+    inputs: &mut VecDeque<i64>,
+    arithmetic: Arithmetic,
+    state: ProcState,
+) -> Option<(ProcState, Option<RunStatus>)> {
+    let ProcState {
+        prog_count: pc,
+        rel_base,
+    } = state;
+    let word = *mem.get(pc)?;
+    let (op, modes) = match *cache {
+        Some(ref mut cache) => cache.split(pc, word)?,
+        None => split_word(word)?,
+    };
+    // The address operand `i` refers to. An immediate operand refers to
+    // its own word.
+    let operand = |i: usize| -> Option<usize> {
+        let at = pc + 1 + i;
+        let word = *mem.get(at)?;
+        let addr = match modes[i] {
+            OperandMode::Position => word,
+            OperandMode::Immediate => return Some(at),
+            OperandMode::Relative => rel_base.checked_add(word)?,
+        };
+        if addr >= 0 && (addr as usize) < mem.len() {
+            Some(addr as usize)
+        } else {
+            None
+        }
+    };
+
+    let mut next = ProcState {
+        prog_count: pc + 1 + op.operand_count(),
+        rel_base,
+    };
+    let mut status = None;
+    let write = match op {
+        Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => {
+            let a = mem[operand(0)?];
+            let b = mem[operand(1)?];
+            if modes[2] == OperandMode::Immediate {
+                return None;
+            }
+            let value = match op {
+                Opcode::Add => arithmetic.add(a, b)?,
+                Opcode::Multiply => arithmetic.mul(a, b)?,
+                Opcode::LessThan => i64::from(a < b),
+                _ => i64::from(a == b),
+            };
+            Some((operand(2)?, value))
+        }
+        Opcode::JumpTrue | Opcode::JumpFalse => {
+            let test = mem[operand(0)?];
+            let target = mem[operand(1)?];
+            if target < 0 {
+                return None;
+            }
+            if (test != 0) == (op == Opcode::JumpTrue) {
+                next.prog_count = target as usize;
+            }
+            None
+        }
+        Opcode::AdjustRelBase => {
+            next.rel_base = rel_base.checked_add(mem[operand(0)?])?;
+            None
+        }
+        Opcode::Input => {
+            if modes[0] == OperandMode::Immediate {
+                return None;
+            }
+            let dest = operand(0)?;
+            match inputs.pop_front() {
+                Some(value) => Some((dest, value)),
+                None => return Some((state, Some(RunStatus::NeedsInput))),
+            }
+        }
+        Opcode::Output => {
+            status = Some(RunStatus::Output(mem[operand(0)?]));
+            None
+        }
+        Opcode::Halt => return Some((state, Some(RunStatus::Halted))),
+    };
+
+    if let Some((dest, value)) = write {
+        mem[dest] = value;
+        if let Some(ref mut cache) = *cache {
+            cache.invalidate(dest);
+        }
+    }
+    Some((next, status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io::{IterInput, LineInput};
+
+    fn run_collect(prog: Vec<i64>) -> Vec<i64> {
+        let mut out = Vec::new();
+        let mut machine = Machine::new(prog);
+        let status = machine.run_until_input(&mut out).unwrap();
+        assert_eq!(status, RunStatus::Halted);
+        out
+    }
+
+    #[test]
+    fn quine() {
+        let prog = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        assert_eq!(run_collect(prog.to_vec()), prog);
+    }
+
+    #[test]
+    fn large_numbers() {
+        assert_eq!(
+            run_collect(vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0]),
+            vec![1219070632396864]
+        );
+        assert_eq!(
+            run_collect(vec![104, 1125899906842624, 99]),
+            vec![1125899906842624]
+        );
+    }
+
+    #[test]
+    fn resumes_after_input() {
+        let mut machine = Machine::new(vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0]);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::NeedsInput));
+        machine.push_input(7);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(7)));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::NeedsInput));
+        assert_eq!(machine.state().prog_count, 4);
+        machine.push_input(8);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(8)));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Halted));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Halted));
+    }
+
+    #[test]
+    fn external_input() {
+        let mut machine = Machine::new(vec![3, 9, 4, 9, 3, 9, 4, 9, 99, 0]);
+        let mut out = Vec::new();
+        let mut input = IterInput(vec![7].into_iter());
+        let status = machine.run(&mut input, &mut out);
+        assert_eq!(status, Ok(RunStatus::NeedsInput));
+        machine.push_input(8);
+        let status = machine.run(&mut input, &mut out);
+        assert_eq!(status, Ok(RunStatus::Halted));
+        assert_eq!(out, vec![7, 8]);
+    }
+
+    #[test]
+    fn bad_input_is_reported() {
+        let mut machine = Machine::new(vec![1101, 1, 1, 9, 3, 9, 99, 0, 0, 0]);
+        let mut input = LineInput::new("seven\n".as_bytes());
+        let err = machine.run(&mut input, &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            err,
+            VmError::UnparsableInput {
+                pc: 4,
+                instr: 3,
+                input: "seven".to_string()
+            }
+        );
+        assert_eq!(machine.state().prog_count, 4);
+    }
+
+    #[test]
+    fn step_reports_events() {
+        let mut machine = Machine::new(vec![1101, 2, 3, 7, 4, 7, 99, 0]);
+        assert_eq!(machine.step(), Ok(None));
+        assert_eq!(machine.step(), Ok(Some(RunStatus::Output(5))));
+        assert_eq!(machine.step(), Ok(Some(RunStatus::Halted)));
+        assert_eq!(machine.state().prog_count, 6);
+    }
+
+    #[test]
+    fn breakpoints_and_budget() {
+        let prog = vec![1101, 2, 3, 7, 4, 7, 99, 0];
+        let mut machine = Machine::new(prog.to_vec());
+        machine.add_breakpoint(4);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::BreakpointHit(4)));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(5)));
+
+        let mut machine = Machine::new(prog);
+        machine.set_budget(Some(1));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::BudgetExhausted));
+        machine.set_budget(Some(10));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(5)));
+        assert_eq!(machine.budget(), Some(9));
+
+        // Single steps ignore the budget and leave it alone.
+        let mut machine = Machine::new(vec![1101, 2, 3, 7, 4, 7, 99, 0]);
+        machine.set_budget(Some(0));
+        assert_eq!(machine.step(), Ok(None));
+        assert_eq!(machine.budget(), Some(0));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::BudgetExhausted));
+    }
+
+    #[test]
+    fn timeout() {
+        let mut machine = Machine::new(vec![1105, 1, 0]);
+        machine.set_timeout(Some(Duration::from_millis(10)));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::TimedOut));
+        machine.set_timeout(None);
+        machine.set_budget(Some(100_000));
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::BudgetExhausted));
+    }
+
+    #[test]
+    fn detects_loops() {
+        let mut machine = Machine::new(vec![1105, 1, 0]);
+        machine.set_loop_detection(true);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::LoopDetected(0)));
+
+        // Flips [7] between 1 and -1 forever.
+        let mut machine = Machine::new(vec![1002, 7, -1, 7, 1105, 1, 0, 1]);
+        machine.set_loop_detection(true);
+        match machine.run_until_output() {
+            Ok(RunStatus::LoopDetected(_)) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // Outputs forever, which is not a hang.
+        let mut machine = Machine::new(vec![104, 1, 1105, 1, 0]);
+        machine.set_loop_detection(true);
+        for _ in 0..10 {
+            assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(1)));
+        }
+
+        // Counting loops terminate and must not be flagged.
+        let prog = ::program::parse_program(include_str!("../../09/input"));
+        let mut machine = Machine::new(prog);
+        machine.set_loop_detection(true);
+        machine.push_input(2);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(76642)));
+    }
+
+    #[test]
+    fn watchpoints_report_writes() {
+        let mut machine = Machine::new(vec![1101, 2, 3, 7, 4, 7, 99, 0]);
+        machine.add_watchpoint(7);
+        assert_eq!(
+            machine.run_until_output(),
+            Ok(RunStatus::WatchpointHit {
+                addr: 7,
+                old: 0,
+                new: 5
+            })
+        );
+        assert_eq!(machine.state().prog_count, 4);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(5)));
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        // The first pass rewrites its own opening `out #5` into `out [5]`
+        // and jumps back to run it again.
+        let prog = vec![104, 5, 1008, 0, 104, 20, 1101, 0, 4, 0, 1005, 20, 0, 99];
+        for &cached in &[true, false] {
+            let mut machine = Machine::new(prog.to_vec());
+            machine.set_decode_cache(cached);
+            let mut out = Vec::new();
+            assert_eq!(machine.run_until_input(&mut out), Ok(RunStatus::Halted));
+            assert_eq!(out, vec![5, 20]);
+        }
+    }
+
+    #[test]
+    fn cache_matches_uncached() {
+        let prog = ::program::parse_program(include_str!("../../09/input"));
+        let mut results = Vec::new();
+        for &cached in &[true, false] {
+            let mut machine = Machine::new(prog.to_vec());
+            machine.set_decode_cache(cached);
+            machine.start_trace();
+            machine.push_input(1);
+            let mut out = Vec::new();
+            assert_eq!(machine.run_until_input(&mut out), Ok(RunStatus::Halted));
+            results.push((out, machine.snapshot(), machine.take_trace()));
+        }
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[test]
+    fn overflow_policy() {
+        let prog = vec![1102, 1 << 40, 1 << 40, 7, 4, 7, 99, 0];
+        let mut machine = Machine::new(prog.to_vec());
+        assert_eq!(
+            machine.run_until_output(),
+            Err(VmError::ArithmeticOverflow { pc: 0, instr: 1102 })
+        );
+        assert_eq!(machine.state().prog_count, 0);
+        machine.set_arithmetic(Arithmetic::Saturating);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(i64::MAX)));
+
+        let mut machine = Machine::new(prog);
+        machine.set_arithmetic(Arithmetic::Wrapping);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(0)));
+    }
+
+    #[test]
+    fn relative_base_overflow() {
+        // The relative base is an address, so it never wraps or saturates.
+        for prog in &[
+            vec![109, i64::MAX, 109, 1, 99],
+            vec![109, i64::MAX, 204, 1, 99],
+        ] {
+            let mut machine = Machine::new(prog.to_vec());
+            machine.set_arithmetic(Arithmetic::Wrapping);
+            assert_eq!(
+                machine.run_until_output(),
+                Err(VmError::ArithmeticOverflow {
+                    pc: 2,
+                    instr: prog[2]
+                })
+            );
+            assert_eq!(machine.state().prog_count, 2);
+            assert_eq!(machine.state().rel_base, i64::MAX);
+        }
+    }
+
+    #[test]
+    fn memory_limit_faults() {
+        let mut machine = Machine::new(vec![21101, 1, 2, 0, 99]);
+        machine.memory_mut().set_max_size(Some(1 << 20));
+        machine.state.rel_base = 1 << 20;
+        assert_eq!(
+            machine.step(),
+            Err(VmError::AddressTooLarge {
+                pc: 0,
+                instr: 21101,
+                addr: 1 << 20
+            })
+        );
+        assert_eq!(machine.state().prog_count, 0);
+
+        let mut machine = Machine::new(vec![1101, 3, 4, 1 << 30, 4, 1 << 30, 99]);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::Output(7)));
+        assert_eq!(machine.memory().dense().len(), 7);
+
+        // An input that faults on its destination keeps its value for the
+        // retry.
+        for &queued in &[true, false] {
+            let mut machine = Machine::new(vec![3, 1 << 20, 4, 1 << 20, 99]);
+            machine.memory_mut().set_max_size(Some(1 << 20));
+            let mut input = IterInput(vec![8, 9].into_iter());
+            if queued {
+                machine.push_input(8);
+                machine.push_input(9);
+            }
+            let mut out = Vec::new();
+            assert_eq!(
+                machine.run(&mut input, &mut out),
+                Err(VmError::AddressTooLarge {
+                    pc: 0,
+                    instr: 3,
+                    addr: 1 << 20
+                })
+            );
+            machine.memory_mut().set_max_size(None);
+            assert_eq!(machine.run(&mut input, &mut out), Ok(RunStatus::Halted));
+            assert_eq!(out, vec![8]);
+        }
+    }
+
+    #[test]
+    fn plain_runs_match_hooked_runs() {
+        // Runs with nothing hooked leave every instruction that might fault
+        // to exec_one; a budget sends them all through it.
+        let cases: &[(&[i64], Option<usize>)] = &[
+            (&[1101, 2, 3, 6, 99, 0, 0], Some(6)),
+            (&[1101, 1, 2, -1, 99], None),
+            (&[1105, 1, -4, 99], None),
+            (&[1101, i64::MAX, 1, 0, 99], None),
+            (&[109, i64::MAX, 22201, 0, 0, 0, 99], None),
+            (&[11101, 1, 1, 0, 99], None),
+            (&[1101, 0, 0, 0, 98], None),
+            (&[1101, 2, 3, 100, 4, 100, 99], None),
+            (&[3, 7, 4, 7, 3, 100, 99, 0], None),
+        ];
+        for &(prog, max_size) in cases {
+            let mut results = Vec::new();
+            for &hooked in &[false, true] {
+                let mut machine = Machine::new(prog.to_vec());
+                machine.memory_mut().set_max_size(max_size);
+                if hooked {
+                    machine.set_budget(Some(u64::MAX));
+                }
+                machine.push_input(7);
+                let mut out = Vec::new();
+                let res = machine.run_until_input(&mut out);
+                results.push((res, out, machine.state(), machine.snapshot()));
+            }
+            assert_eq!(results[0], results[1], "{:?}", prog);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+extern crate intcode;
+
+use std::io::Read;
+
+fn read_file(path: &str) -> String {
+    let file = std::fs::File::open(path).unwrap();
+    let mut reader = std::io::BufReader::new(file);
+    let mut file_string = String::new();
+    reader
+        .read_to_string(&mut file_string)
+        .expect("Unable to read file");
+    file_string
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let usage = "Usage: intcode <disasm program | asm source | cfg program [dot-file] | \
+                 ascii program | debug program [inputs...] | profile program [inputs...] | \
+                 trace record program file [inputs...] | trace show file | trace diff a b>";
+    if args.len() < 3 {
+        panic!("{}", usage);
+    }
+
+    match args[1].as_str() {
+        "disasm" if args.len() == 3 => {
+            let program = intcode::load_program(&args[2]);
+            print!("{}", intcode::listing(&program));
+        }
+        "asm" if args.len() == 3 => match intcode::assemble(&read_file(&args[2])) {
+            Ok(program) => {
+                let words: Vec<String> = program.iter().map(|x| x.to_string()).collect();
+                println!("{}", words.join(","));
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        "cfg" if args.len() <= 4 => {
+            let cfg = intcode::build_cfg(&intcode::load_program(&args[2]));
+            print!("{}", cfg.summary());
+            if let Some(path) = args.get(3) {
+                std::fs::write(path, cfg.to_dot()).unwrap_or_else(|e| panic!("{}: {}", path, e));
+            }
+        }
+        "ascii" if args.len() == 3 => {
+            let machine = intcode::Machine::new(intcode::load_program(&args[2]));
+            match intcode::AsciiMachine::new(machine).interactive_stdio() {
+                Ok(status) => eprintln!("{:?}", status),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        "debug" => {
+            let inputs = parse_inputs(&args[3..]);
+            let mut machine = intcode::Machine::new(intcode::load_program(&args[2]));
+            let mut debugger = intcode::Debugger::stdio();
+            let mut input = intcode::IterInput(inputs.into_iter());
+            match debugger.run(&mut machine, &mut input, &mut intcode::StdoutOutput) {
+                Ok(status) => println!("{:?}", status),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        "profile" => {
+            let mut machine = intcode::Machine::new(intcode::load_program(&args[2]));
+            machine.start_profile();
+            let mut input = intcode::IterInput(parse_inputs(&args[3..]).into_iter());
+            match machine.run(&mut input, &mut intcode::StdoutOutput) {
+                Ok(status) => println!("{:?}", status),
+                Err(e) => eprintln!("{}", e),
+            }
+            let profile = machine.take_profile().unwrap();
+            print!("{}", profile.report(machine.memory(), 10));
+        }
+        "trace" if args.len() >= 4 => match args[2].as_str() {
+            "record" if args.len() >= 5 => {
+                let mut machine = intcode::Machine::new(intcode::load_program(&args[3]));
+                machine.start_trace();
+                let mut input = intcode::IterInput(parse_inputs(&args[5..]).into_iter());
+                let res = machine.run(&mut input, &mut intcode::StdoutOutput);
+                let trace = machine.take_trace().unwrap();
+                match res {
+                    Ok(status) => println!("{:?} after {} instructions", status, trace.len()),
+                    Err(e) => eprintln!("{}", e),
+                }
+                trace.save(&args[4]).unwrap_or_else(|e| panic!("{}", e));
+            }
+            "show" if args.len() == 4 => {
+                for record in load_trace(&args[3]).records() {
+                    println!("{}", record);
+                }
+            }
+            "diff" if args.len() == 5 => {
+                let a = load_trace(&args[3]).records();
+                let b = load_trace(&args[4]).records();
+                match intcode::first_divergence(&a, &b) {
+                    None => println!("Traces match ({} instructions)", a.len()),
+                    Some(i) => {
+                        println!("Traces diverge at instruction {}", i);
+                        let show = |r: Option<&intcode::TraceRecord>| match r {
+                            Some(r) => r.to_string(),
+                            None => "<end of trace>".to_string(),
+                        };
+                        println!("  a: {}", show(a.get(i)));
+                        println!("  b: {}", show(b.get(i)));
+                    }
+                }
+            }
+            _ => panic!("{}", usage),
+        },
+        _ => panic!("{}", usage),
+    }
+}
+
+fn parse_inputs(args: &[String]) -> Vec<i64> {
+    args.iter()
+        .map(|x| x.parse().expect("Inputs must be integers"))
+        .collect()
+}
+
+fn load_trace(path: &str) -> intcode::Trace {
+    intcode::Trace::load(path).unwrap_or_else(|e| panic!("{}", e))
+}
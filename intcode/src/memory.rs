@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+
+/// Addresses below this are kept in one flat vector.
+pub const DEFAULT_DENSE_LIMIT: usize = 1 << 16;
+/// No puzzle comes anywhere near this; it only stops runaway addresses.
+pub const DEFAULT_MAX_SIZE: usize = 1 << 32;
+
+/// An access at or beyond the configured maximum memory size.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MemoryLimit {
+    pub addr: usize,
+    pub max_size: usize,
+}
+
+impl fmt::Display for MemoryLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "address {} beyond memory limit {}",
+            self.addr, self.max_size
+        )
+    }
+}
+
+impl Error for MemoryLimit {}
+
+/// Intcode memory. Every address reads as 0 until written. Low addresses,
+/// where the program and its working data live, are stored densely and
+/// grow on demand; anything above the dense limit lives in fixed size
+/// pages that are only allocated when written, so a stray write to a huge
+/// address costs one page rather than gigabytes.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Memory {
+    dense: Vec<i64>,
+    dense_limit: usize,
+    pages: HashMap<usize, Box<[i64]>>,
+    max_size: Option<usize>,
+}
+
+impl Memory {
+    pub fn new(prog: Vec<i64>) -> Memory {
+        Memory::with_dense_limit(prog, DEFAULT_DENSE_LIMIT)
+    }
+
+    /// The program is always stored densely, even if it is longer than
+    /// `dense_limit`.
+    pub fn with_dense_limit(prog: Vec<i64>, dense_limit: usize) -> Memory {
+        Memory {
+            dense_limit: dense_limit.max(prog.len()),
+            dense: prog,
+            pages: HashMap::new(),
+            max_size: Some(DEFAULT_MAX_SIZE),
+        }
+    }
+
+    /// Makes every access at or above `max_size` fail. `None` removes the
+    /// limit.
+    pub fn set_max_size(&mut self, max_size: Option<usize>) {
+        self.max_size = max_size;
+    }
+
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    fn check(&self, addr: usize) -> Result<(), MemoryLimit> {
+        match self.max_size {
+            Some(max_size) if addr >= max_size => Err(MemoryLimit { addr, max_size }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get(&self, addr: usize) -> Result<i64, MemoryLimit> {
+        self.check(addr)?;
+        Ok(if addr < self.dense_limit {
+            self.dense.get(addr).cloned().unwrap_or(0)
+        } else {
+            self.pages
+                .get(&(addr >> PAGE_BITS))
+                .map_or(0, |page| page[addr & (PAGE_SIZE - 1)])
+        })
+    }
+
+    pub fn get_mut(&mut self, addr: usize) -> Result<&mut i64, MemoryLimit> {
+        self.check(addr)?;
+        if addr < self.dense_limit {
+            if addr >= self.dense.len() {
+                self.dense.resize(addr + 1, 0);
+            }
+            Ok(&mut self.dense[addr])
+        } else {
+            let page = self
+                .pages
+                .entry(addr >> PAGE_BITS)
+                .or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice());
+            Ok(&mut page[addr & (PAGE_SIZE - 1)])
+        }
+    }
+
+    /// The densely stored words: the program followed by whatever low
+    /// memory has been touched since.
+    pub fn dense(&self) -> &[i64] {
+        &self.dense
+    }
+
+    /// The densely stored words below the memory limit, for callers that
+    /// do their own bounds checks. Anything beyond them has to go through
+    /// `get` and `get_mut`.
+    pub fn dense_within_limit_mut(&mut self) -> &mut [i64] {
+        let len = self
+            .max_size
+            .map_or(self.dense.len(), |max_size| max_size.min(self.dense.len()));
+        &mut self.dense[..len]
+    }
+
+    /// Nonzero words stored outside the dense region, in address order.
+    pub fn sparse_words(&self) -> Vec<(usize, i64)> {
+        let mut words: Vec<(usize, i64)> = self
+            .pages
+            .iter()
+            .flat_map(|(&page, values)| {
+                values
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &v)| v != 0)
+                    .map(move |(i, &v)| ((page << PAGE_BITS) + i, v))
+            })
+            .collect();
+        words.sort();
+        words
+    }
+
+    /// Copies out `len` words starting at `start`, reading addresses past
+    /// the limit as 0.
+    pub fn words(&self, start: usize, len: usize) -> Vec<i64> {
+        (start..start.saturating_add(len))
+            .map(|a| self.get(a).unwrap_or(0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dense_and_sparse() {
+        let mut mem = Memory::with_dense_limit(vec![1, 2, 3], 8);
+        assert_eq!(mem.get(100), Ok(0));
+        *mem.get_mut(5).unwrap() = 6;
+        *mem.get_mut(1 << 30).unwrap() = 9;
+        *mem.get_mut(9).unwrap() = -1;
+        assert_eq!(mem.dense(), &[1, 2, 3, 0, 0, 6][..]);
+        assert_eq!(mem.sparse_words(), vec![(9, -1), (1 << 30, 9)]);
+        assert_eq!(mem.get(1 << 30), Ok(9));
+        assert_eq!(mem.words(4, 3), vec![0, 6, 0]);
+    }
+
+    #[test]
+    fn enforces_limit() {
+        let mut mem = Memory::new(vec![99]);
+        mem.set_max_size(Some(16));
+        assert_eq!(mem.get(15), Ok(0));
+        let err = MemoryLimit {
+            addr: 16,
+            max_size: 16,
+        };
+        assert_eq!(mem.get(16), Err(err));
+        assert_eq!(mem.get_mut(16).map(|_| ()), Err(err));
+        mem.set_max_size(None);
+        assert!(mem.get_mut(usize::MAX).is_ok());
+
+        let mut mem = Memory::new(vec![1, 2, 3]);
+        mem.set_max_size(Some(2));
+        assert_eq!(mem.dense_within_limit_mut(), &mut [1, 2][..]);
+    }
+}
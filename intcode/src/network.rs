@@ -0,0 +1,567 @@
+//! Several machines wired together by queues, like the day 7 amplifiers.
+//!
+//! Every edge carries values from one machine's output to another's
+//! input through its own queue. A machine with several outgoing edges
+//! sends each output down all of them; one with several incoming edges
+//! reads from the first of them, in the order they were connected, that
+//! has a value waiting. Values pushed straight to a machine with
+//! `push_input`, such as phase settings, are read before any edge.
+//!
+//! A network can also run with every machine on its own thread; see
+//! `Network::run_threaded`.
+
+use error::VmError;
+use io::Input;
+use machine::{Machine, RunStatus};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How often a thread blocked on input checks whether the whole network
+/// has deadlocked.
+const DEADLOCK_POLL: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Debug)]
+struct Edge {
+    from: usize,
+    to: usize,
+    queue: VecDeque<i64>,
+}
+
+/// A machine waiting for input that no edge can provide.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Blocked {
+    pub machine: usize,
+    /// Address of the input instruction it's stuck on.
+    pub pc: usize,
+    /// Machines whose edges it reads from, all of them empty.
+    pub waiting_on: Vec<usize>,
+}
+
+/// Why `Network::run` returned.
+#[derive(Clone, PartialEq, Debug)]
+pub enum NetworkStatus {
+    AllHalted,
+    /// Nothing can make progress: every machine still running is blocked.
+    Deadlock(Vec<Blocked>),
+    /// A machine stopped for a reason of its own: a breakpoint, watchpoint
+    /// or exhausted budget.
+    Stopped {
+        machine: usize,
+        status: RunStatus,
+    },
+}
+
+impl fmt::Display for NetworkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NetworkStatus::AllHalted => write!(f, "all machines halted"),
+            NetworkStatus::Deadlock(ref blocked) => {
+                write!(f, "deadlock:")?;
+                for b in blocked {
+                    write!(
+                        f,
+                        " machine {} at pc {} waits on {:?};",
+                        b.machine, b.pc, b.waiting_on
+                    )?;
+                }
+                Ok(())
+            }
+            NetworkStatus::Stopped { machine, status } => {
+                write!(f, "machine {} stopped: {:?}", machine, status)
+            }
+        }
+    }
+}
+
+/// A fault in one of the machines of a network.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NetworkError {
+    pub machine: usize,
+    pub error: VmError,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "machine {}: {}", self.machine, self.error)
+    }
+}
+
+impl Error for NetworkError {}
+
+/// Reads a machine's incoming edges, counting what it takes.
+struct EdgeInput<'a> {
+    edges: &'a mut [Edge],
+    machine: usize,
+    taken: usize,
+}
+
+impl<'a> Input for EdgeInput<'a> {
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        let machine = self.machine;
+        let value = self
+            .edges
+            .iter_mut()
+            .filter(|e| e.to == machine)
+            .filter_map(|e| e.queue.pop_front())
+            .next();
+        if value.is_some() {
+            self.taken += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// What the threads of `run_threaded` share to spot a deadlock: which
+/// machines are still running, which of those are waiting for input and
+/// how many values are on their way to each.
+struct Waits {
+    live: Vec<bool>,
+    waiting: Vec<bool>,
+    pending: Vec<usize>,
+    deadlock: bool,
+}
+
+impl Waits {
+    /// Once every live machine is waiting with nothing on its way, none of
+    /// them can ever send again.
+    fn check_deadlock(&mut self) -> bool {
+        if !self.deadlock {
+            self.deadlock = (0..self.live.len())
+                .all(|i| !self.live[i] || (self.waiting[i] && self.pending[i] == 0));
+        }
+        self.deadlock
+    }
+}
+
+/// A machine's end of the channels in `run_threaded`.
+struct ThreadInput {
+    machine: usize,
+    rx: Receiver<i64>,
+    waits: Arc<Mutex<Waits>>,
+}
+
+impl ThreadInput {
+    fn received(&self, value: i64) -> Result<Option<i64>, String> {
+        let mut waits = self.waits.lock().unwrap();
+        waits.waiting[self.machine] = false;
+        waits.pending[self.machine] -= 1;
+        Ok(Some(value))
+    }
+}
+
+/// Blocks until a value arrives, the senders all hang up or the network
+/// deadlocks; the last two read as no input.
+impl Input for ThreadInput {
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        match self.rx.try_recv() {
+            Ok(value) => return self.received(value),
+            Err(TryRecvError::Disconnected) => return Ok(None),
+            Err(TryRecvError::Empty) => (),
+        }
+        self.waits.lock().unwrap().waiting[self.machine] = true;
+        loop {
+            match self.rx.recv_timeout(DEADLOCK_POLL) {
+                Ok(value) => return self.received(value),
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.waits.lock().unwrap().check_deadlock() {
+                        break;
+                    }
+                }
+            }
+        }
+        self.waits.lock().unwrap().waiting[self.machine] = false;
+        Ok(None)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Network {
+    machines: Vec<Machine>,
+    edges: Vec<Edge>,
+    halted: Vec<bool>,
+    outputs: Vec<Vec<i64>>,
+}
+
+impl Network {
+    /// A network with no edges yet; see `connect`.
+    pub fn new(machines: Vec<Machine>) -> Network {
+        let n = machines.len();
+        Network {
+            machines,
+            edges: Vec::new(),
+            halted: vec![false; n],
+            outputs: vec![Vec::new(); n],
+        }
+    }
+
+    /// Each machine feeds the next.
+    pub fn chain(machines: Vec<Machine>) -> Network {
+        let mut network = Network::new(machines);
+        for i in 1..network.len() {
+            network.connect(i - 1, i);
+        }
+        network
+    }
+
+    /// A chain whose last machine feeds the first.
+    pub fn ring(machines: Vec<Machine>) -> Network {
+        let mut network = Network::chain(machines);
+        if !network.machines.is_empty() {
+            let last = network.len() - 1;
+            network.connect(last, 0);
+        }
+        network
+    }
+
+    /// Machine 0 feeds every other machine.
+    pub fn fan_out(machines: Vec<Machine>) -> Network {
+        let mut network = Network::new(machines);
+        for i in 1..network.len() {
+            network.connect(0, i);
+        }
+        network
+    }
+
+    /// Adds an edge from `from`'s output to `to`'s input and returns its
+    /// index.
+    pub fn connect(&mut self, from: usize, to: usize) -> usize {
+        assert!(from < self.len() && to < self.len(), "No such machine");
+        self.edges.push(Edge {
+            from,
+            to,
+            queue: VecDeque::new(),
+        });
+        self.edges.len() - 1
+    }
+
+    /// Queues a value on an edge as if its source had output it.
+    pub fn send(&mut self, edge: usize, value: i64) {
+        self.edges[edge].queue.push_back(value);
+    }
+
+    /// Queues a value for a machine ahead of anything on its edges.
+    pub fn push_input(&mut self, machine: usize, value: i64) {
+        self.machines[machine].push_input(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    pub fn machine(&self, i: usize) -> &Machine {
+        &self.machines[i]
+    }
+
+    pub fn machine_mut(&mut self, i: usize) -> &mut Machine {
+        &mut self.machines[i]
+    }
+
+    /// Everything machine `i` has output so far.
+    pub fn outputs(&self, i: usize) -> &[i64] {
+        &self.outputs[i]
+    }
+
+    pub fn is_halted(&self, i: usize) -> bool {
+        self.halted[i]
+    }
+
+    /// Gives each machine that hasn't halted one turn, in order, running it
+    /// until it halts or blocks on input. Returns a status once the network
+    /// can't go on, and `None` while it still can.
+    pub fn round(&mut self) -> Result<Option<NetworkStatus>, NetworkError> {
+        let mut progress = false;
+        for i in 0..self.machines.len() {
+            if self.halted[i] {
+                continue;
+            }
+            let mut input = EdgeInput {
+                edges: &mut self.edges,
+                machine: i,
+                taken: 0,
+            };
+            let mut out = Vec::new();
+            let status = self.machines[i].run(&mut input, &mut out);
+            progress |= input.taken > 0 || !out.is_empty();
+            for &value in &out {
+                for edge in self.edges.iter_mut().filter(|e| e.from == i) {
+                    edge.queue.push_back(value);
+                }
+            }
+            self.outputs[i].extend(out);
+            match status {
+                Ok(RunStatus::Halted) => {
+                    self.halted[i] = true;
+                    progress = true;
+                }
+                Ok(RunStatus::NeedsInput) => (),
+                Ok(status) => return Ok(Some(NetworkStatus::Stopped { machine: i, status })),
+                Err(error) => return Err(NetworkError { machine: i, error }),
+            }
+        }
+        if self.halted.iter().all(|&h| h) {
+            Ok(Some(NetworkStatus::AllHalted))
+        } else if !progress {
+            Ok(Some(NetworkStatus::Deadlock(self.blocked())))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Runs rounds until every machine has halted, nothing can move or a
+    /// machine stops or faults.
+    pub fn run(&mut self) -> Result<NetworkStatus, NetworkError> {
+        loop {
+            if let Some(status) = self.round()? {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Runs every machine on its own thread until they have all finished,
+    /// connected by channels instead of queues. Reads block until a value
+    /// arrives, and a machine that halts closes its outputs, so the
+    /// machines waiting on it finish too and are reported as blocked. When
+    /// every machine still running is waiting for input with none on its
+    /// way, as in a cycle of machines all waiting on each other, they all
+    /// give up and the network is reported as deadlocked.
+    ///
+    /// All incoming edges of a machine share one channel, so a machine
+    /// reads its inputs in the order they arrive rather than edge by edge.
+    /// Whatever is still queued at the end is left in the machines' own
+    /// input queues.
+    pub fn run_threaded(&mut self) -> Result<NetworkStatus, NetworkError> {
+        let n = self.len();
+        let (senders, receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) =
+            (0..n).map(|_| channel()).unzip();
+        let mut waits = Waits {
+            live: self.halted.iter().map(|&h| !h).collect(),
+            waiting: vec![false; n],
+            pending: vec![0; n],
+            deadlock: false,
+        };
+        for edge in &mut self.edges {
+            for value in edge.queue.drain(..) {
+                senders[edge.to].send(value).unwrap();
+                waits.pending[edge.to] += 1;
+            }
+        }
+        let waits = Arc::new(Mutex::new(waits));
+
+        let machines = mem::take(&mut self.machines);
+        let handles: Vec<_> = machines
+            .into_iter()
+            .zip(receivers)
+            .enumerate()
+            .map(|(i, (mut machine, rx))| {
+                let halted = self.halted[i];
+                let outs: Vec<(usize, Sender<i64>)> = self
+                    .edges
+                    .iter()
+                    .filter(|e| e.from == i)
+                    .map(|e| (e.to, senders[e.to].clone()))
+                    .collect();
+                let waits = waits.clone();
+                let mut input = ThreadInput {
+                    machine: i,
+                    rx,
+                    waits: waits.clone(),
+                };
+                thread::spawn(move || {
+                    let mut out = Vec::new();
+                    let status = if halted {
+                        Ok(RunStatus::Halted)
+                    } else {
+                        machine.run(&mut input, &mut |value| {
+                            let mut waits = waits.lock().unwrap();
+                            for &(to, ref tx) in &outs {
+                                if tx.send(value).is_ok() {
+                                    waits.pending[to] += 1;
+                                }
+                            }
+                            out.push(value);
+                        })
+                    };
+                    waits.lock().unwrap().live[i] = false;
+                    (machine, status, out, input.rx)
+                })
+            })
+            .collect();
+        drop(senders);
+
+        let mut result = Ok(NetworkStatus::AllHalted);
+        let mut receivers = Vec::new();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let (machine, status, out, rx) = handle.join().expect("Machine thread panicked");
+            self.machines.push(machine);
+            self.outputs[i].extend(out);
+            receivers.push(rx);
+            match status {
+                Ok(RunStatus::Halted) => self.halted[i] = true,
+                Ok(RunStatus::NeedsInput) => (),
+                Ok(status) => {
+                    if let Ok(NetworkStatus::AllHalted) = result {
+                        result = Ok(NetworkStatus::Stopped { machine: i, status });
+                    }
+                }
+                Err(error) => {
+                    if result.is_ok() {
+                        result = Err(NetworkError { machine: i, error });
+                    }
+                }
+            }
+        }
+        for (machine, rx) in self.machines.iter_mut().zip(receivers) {
+            for value in rx.try_iter() {
+                machine.push_input(value);
+            }
+        }
+        match result {
+            Ok(NetworkStatus::AllHalted) if !self.halted.iter().all(|&h| h) => {
+                Ok(NetworkStatus::Deadlock(self.blocked()))
+            }
+            result => result,
+        }
+    }
+
+    fn blocked(&self) -> Vec<Blocked> {
+        (0..self.len())
+            .filter(|&i| !self.halted[i])
+            .map(|i| Blocked {
+                machine: i,
+                pc: self.machines[i].state().prog_count,
+                waiting_on: self
+                    .edges
+                    .iter()
+                    .filter(|e| e.to == i)
+                    .map(|e| e.from)
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads two values and outputs their sum.
+    const SUM: [i64; 9] = [3, 0, 3, 1, 1, 0, 1, 0, 4];
+
+    fn sum_machines(n: usize) -> Vec<Machine> {
+        let mut prog = SUM.to_vec();
+        prog.extend_from_slice(&[0, 99]);
+        (0..n).map(|_| Machine::new(prog.clone())).collect()
+    }
+
+    #[test]
+    fn chain_and_fan_out() {
+        let mut chain = Network::chain(sum_machines(3));
+        for i in 0..3 {
+            chain.push_input(i, 10);
+        }
+        chain.push_input(0, 1);
+        assert_eq!(chain.run(), Ok(NetworkStatus::AllHalted));
+        assert_eq!(chain.outputs(2), &[31][..]);
+
+        let mut fan = Network::fan_out(sum_machines(3));
+        fan.push_input(0, 2);
+        fan.push_input(0, 3);
+        fan.send(0, 100);
+        fan.send(1, 200);
+        assert_eq!(fan.run(), Ok(NetworkStatus::AllHalted));
+        assert_eq!((fan.outputs(1), fan.outputs(2)), (&[105][..], &[205][..]));
+    }
+
+    #[test]
+    fn reports_deadlock() {
+        let mut ring = Network::ring(sum_machines(2));
+        ring.push_input(0, 1);
+        match ring.run() {
+            Ok(NetworkStatus::Deadlock(blocked)) => assert_eq!(
+                blocked,
+                vec![
+                    Blocked {
+                        machine: 0,
+                        pc: 2,
+                        waiting_on: vec![1],
+                    },
+                    Blocked {
+                        machine: 1,
+                        pc: 0,
+                        waiting_on: vec![0],
+                    },
+                ]
+            ),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn threaded_chain() {
+        let mut chain = Network::chain(sum_machines(3));
+        for i in 0..2 {
+            chain.push_input(i, 10);
+        }
+        chain.send(0, 5);
+        chain.push_input(0, 1);
+        assert_eq!(
+            chain.run_threaded(),
+            Ok(NetworkStatus::Deadlock(vec![Blocked {
+                machine: 2,
+                pc: 2,
+                waiting_on: vec![1],
+            }]))
+        );
+        assert_eq!(chain.outputs(1), &[15][..]);
+        assert!(chain.is_halted(0) && chain.is_halted(1));
+        // Machine 1 read the 5 sent ahead and never got to machine 0's 11.
+        assert_eq!(chain.machine(1).snapshot().inputs, vec![11]);
+
+        chain.push_input(2, 0);
+        assert_eq!(chain.run_threaded(), Ok(NetworkStatus::AllHalted));
+        assert_eq!(chain.outputs(2), &[15][..]);
+    }
+
+    #[test]
+    fn threaded_deadlock() {
+        let mut ring = Network::ring(sum_machines(2));
+        ring.push_input(0, 1);
+        let expected = ring.clone().run();
+        assert_eq!(ring.run_threaded(), expected);
+
+        // A machine waiting on its own output before it has made any.
+        let mut network = Network::new(vec![Machine::new(vec![3, 0, 3, 1, 4, 0, 99])]);
+        network.connect(0, 0);
+        network.push_input(0, 1);
+        assert_eq!(
+            network.run_threaded(),
+            Ok(NetworkStatus::Deadlock(vec![Blocked {
+                machine: 0,
+                pc: 2,
+                waiting_on: vec![0],
+            }]))
+        );
+    }
+
+    #[test]
+    fn graph_with_faults() {
+        let mut network = Network::new(sum_machines(2));
+        *network.machine_mut(1) = Machine::new(vec![3, 0, 42]);
+        let edge = network.connect(0, 1);
+        network.send(edge, 7);
+        let err = network.run().unwrap_err();
+        assert_eq!(err.machine, 1);
+        assert_eq!(err.error.pc(), 2);
+    }
+}
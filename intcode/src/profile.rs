@@ -0,0 +1,227 @@
+//! Execution profiling: counts of executed instructions by address and
+//! opcode, memory traffic by address, I/O events and taken backward jumps,
+//! which is where a program's loops close.
+
+use disasm::{decode_at, format_listing, Decoded, DisasmLine};
+use instr::{Opcode, OperandMode, RawInstr};
+use memory::Memory;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::Hash;
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Profile {
+    pub instructions: u64,
+    pub inputs: u64,
+    pub outputs: u64,
+    pub by_addr: HashMap<usize, u64>,
+    pub by_opcode: HashMap<Opcode, u64>,
+    pub reads: HashMap<usize, u64>,
+    pub writes: HashMap<usize, u64>,
+    /// Taken jumps to an immediate address at or before the jump, keyed by
+    /// `(jump address, target)`. Computed jumps are left out since going
+    /// backwards through one is usually a return, not a loop.
+    pub back_edges: HashMap<(usize, usize), u64>,
+}
+
+/// A loop found from a taken backward jump: the code from `header` up to
+/// and including the jump at `latch`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct HotLoop {
+    pub header: usize,
+    pub latch: usize,
+    pub iterations: u64,
+    /// Instructions executed inside the loop's address range.
+    pub instructions: u64,
+}
+
+fn bump<K: Hash + Eq>(counts: &mut HashMap<K, u64>, key: K) {
+    *counts.entry(key).or_insert(0) += 1;
+}
+
+/// The `n` largest counts, biggest first and lowest key first on ties.
+fn top<K: Copy + Ord>(counts: &HashMap<K, u64>, n: usize) -> Vec<(K, u64)> {
+    let mut entries: Vec<(K, u64)> = counts.iter().map(|(&k, &c)| (k, c)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(n);
+    entries
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / whole as f64
+    }
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    /// Counts one executed instruction. `rel_base` is the relative base it
+    /// ran with and `next_pc` the address execution continued at.
+    pub fn record(
+        &mut self,
+        pc: usize,
+        rel_base: i64,
+        raw: &RawInstr,
+        write: Option<usize>,
+        next_pc: usize,
+    ) {
+        self.instructions += 1;
+        bump(&mut self.by_addr, pc);
+        bump(&mut self.by_opcode, raw.op);
+
+        let count = raw.op.operand_count();
+        let reads = if raw.op.writes_last() {
+            count - 1
+        } else {
+            count
+        };
+        for i in 0..reads {
+            let addr = match raw.modes[i] {
+                OperandMode::Position => raw.operands[i],
+                OperandMode::Immediate => continue,
+                OperandMode::Relative => rel_base + raw.operands[i],
+            };
+            bump(&mut self.reads, addr as usize);
+        }
+        if let Some(addr) = write {
+            bump(&mut self.writes, addr);
+        }
+
+        match raw.op {
+            Opcode::Input => self.inputs += 1,
+            Opcode::Output => self.outputs += 1,
+            Opcode::JumpTrue | Opcode::JumpFalse
+                if next_pc <= pc && raw.modes[1] == OperandMode::Immediate =>
+            {
+                bump(&mut self.back_edges, (pc, next_pc))
+            }
+            _ => (),
+        }
+    }
+
+    /// Loops by the number of instructions executed inside them, hottest
+    /// first.
+    pub fn hot_loops(&self) -> Vec<HotLoop> {
+        let mut loops: Vec<HotLoop> = self
+            .back_edges
+            .iter()
+            .map(|(&(latch, header), &iterations)| HotLoop {
+                header,
+                latch,
+                iterations,
+                instructions: (header..=latch).filter_map(|a| self.by_addr.get(&a)).sum(),
+            })
+            .collect();
+        loops.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then(a.header.cmp(&b.header))
+        });
+        loops
+    }
+
+    /// A text report of the `n` busiest of everything, with instructions
+    /// disassembled from `mem`. Self-modified code shows as it is now, not
+    /// as it was when it ran.
+    pub fn report(&self, mem: &Memory, n: usize) -> String {
+        let total = self.instructions;
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{} instructions, {} inputs, {} outputs",
+            total, self.inputs, self.outputs
+        )
+        .unwrap();
+
+        let mut ops: Vec<(Opcode, u64)> = self.by_opcode.iter().map(|(&k, &c)| (k, c)).collect();
+        ops.sort_by(|a, b| b.1.cmp(&a.1).then((a.0 as u8).cmp(&(b.0 as u8))));
+        writeln!(out, "by opcode:").unwrap();
+        for (op, count) in ops {
+            writeln!(
+                out,
+                "  {:<4} {:>12} {:>5.1}%",
+                op.mnemonic(),
+                count,
+                percent(count, total)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "hot loops:").unwrap();
+        for l in self.hot_loops().iter().take(n) {
+            writeln!(
+                out,
+                "  {:>6}..{:<6} {:>12} iterations {:>12} instructions {:>5.1}%",
+                l.header,
+                l.latch,
+                l.iterations,
+                l.instructions,
+                percent(l.instructions, total)
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "busiest instructions:").unwrap();
+        for (addr, count) in top(&self.by_addr, n) {
+            let decoded = match decode_at(&mem.words(addr, 4), 0) {
+                Some((op, operands)) => Decoded::Instr { op, operands },
+                None => Decoded::Data(mem.get(addr).unwrap_or(0)),
+            };
+            let line = format_listing(&[DisasmLine { addr, decoded }]);
+            writeln!(
+                out,
+                "  {:>12} {:>5.1}% {}",
+                count,
+                percent(count, total),
+                line.trim_end()
+            )
+            .unwrap();
+        }
+
+        for (title, counts) in &[("most read:", &self.reads), ("most written:", &self.writes)] {
+            writeln!(out, "{}", title).unwrap();
+            for (addr, count) in top(counts, n) {
+                writeln!(out, "  {:>6} {:>12}", addr, count).unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use machine::{Machine, RunStatus};
+
+    #[test]
+    fn counts_a_loop() {
+        // Counts [20] down from 3, outputting each value.
+        let prog = vec![
+            4, 20, 1001, 20, -1, 20, 1005, 20, 0, 99, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3,
+        ];
+        let mut machine = Machine::new(prog);
+        machine.start_profile();
+        let mut out = Vec::new();
+        assert_eq!(machine.run_until_input(&mut out), Ok(RunStatus::Halted));
+        assert_eq!(out, vec![3, 2, 1]);
+        let profile = machine.take_profile().unwrap();
+
+        assert_eq!(profile.instructions, 10);
+        assert_eq!(profile.outputs, 3);
+        assert_eq!(profile.by_addr[&2], 3);
+        assert_eq!(profile.reads[&20], 9);
+        assert_eq!(profile.writes[&20], 3);
+        let loops = profile.hot_loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!((loops[0].header, loops[0].latch), (0, 6));
+        assert_eq!((loops[0].iterations, loops[0].instructions), (2, 9));
+
+        let report = profile.report(machine.memory(), 3);
+        assert!(report.contains("0..6"));
+        assert!(report.contains(" 30.0%      0  out [20]"));
+    }
+}
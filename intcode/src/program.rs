@@ -0,0 +1,261 @@
+use machine::Machine;
+use memory::DEFAULT_DENSE_LIMIT;
+use std::fmt;
+use std::io::Read;
+use std::time::Duration;
+
+/// Parses a comma separated Intcode program, ignoring any whitespace.
+pub fn parse_program(text: &str) -> Vec<i64> {
+    let no_whitespace_str: String = text.split_whitespace().collect::<Vec<&str>>().join("");
+    no_whitespace_str
+        .split(',')
+        .map(|x| x.parse::<i64>().unwrap())
+        .collect()
+}
+
+pub fn load_program(path: &str) -> Vec<i64> {
+    let file = std::fs::File::open(path).unwrap();
+    let mut reader = std::io::BufReader::new(file);
+    let mut file_string = String::new();
+    reader
+        .read_to_string(&mut file_string)
+        .expect("Unable to read file");
+
+    parse_program(&file_string)
+}
+
+/// Sets the word at `addr` to `value` before a run, like the day 2 noun
+/// and verb or the day 17 wake-up call.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Patch {
+    pub addr: usize,
+    pub value: i64,
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.addr, self.value)
+    }
+}
+
+/// Parses `addr=value` patches separated by commas or whitespace, e.g.
+/// `1=12,2=2`. Anything after a `#` on a line is a comment, so patch files
+/// read the same way.
+pub fn parse_patches(text: &str) -> Result<Vec<Patch>, String> {
+    text.lines()
+        .flat_map(|line| {
+            line.split('#')
+                .next()
+                .unwrap()
+                .split(|c: char| c == ',' || c.is_whitespace())
+        })
+        .filter(|spec| !spec.is_empty())
+        .map(|spec| {
+            let bad = || format!("Bad patch {:?}, expected addr=value", spec);
+            let mut parts = spec.splitn(2, '=');
+            let addr = parts.next().unwrap().parse().map_err(|_| bad())?;
+            let value = parts.next().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+            Ok(Patch { addr, value })
+        })
+        .collect()
+}
+
+pub fn load_patches(path: &str) -> Result<Vec<Patch>, String> {
+    let mut text = String::new();
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("{}: {}", path, e))?;
+    parse_patches(&text)
+}
+
+/// Removes every `--patch <patches>` and `--patch-file <file>` pair from
+/// command line arguments and returns the patches they give, in order.
+pub fn take_patch_args(args: &mut Vec<String>) -> Result<Vec<Patch>, String> {
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let load: fn(&str) -> Result<Vec<Patch>, String> = match args[i].as_str() {
+            "--patch" => parse_patches,
+            "--patch-file" => load_patches,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        let spec = args
+            .get(i + 1)
+            .ok_or_else(|| format!("{} needs an argument", args[i]))?;
+        patches.extend(load(spec)?);
+        args.drain(i..i + 2);
+    }
+    Ok(patches)
+}
+
+/// Limits on a run given on the command line; see `take_limit_args`.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Limits {
+    pub budget: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+impl Limits {
+    pub fn apply(&self, machine: &mut Machine) {
+        machine.set_budget(self.budget);
+        machine.set_timeout(self.timeout);
+    }
+}
+
+/// Removes every `--budget <instructions>` and `--timeout <seconds>` pair
+/// from command line arguments and returns the limits they give. A later
+/// pair overrides an earlier one.
+pub fn take_limit_args(args: &mut Vec<String>) -> Result<Limits, String> {
+    let mut limits = Limits::default();
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] != "--budget" && args[i] != "--timeout" {
+            i += 1;
+            continue;
+        }
+        let value = args
+            .get(i + 1)
+            .ok_or_else(|| format!("{} needs an argument", args[i]))?;
+        let bad = || format!("Bad {} {:?}", args[i], value);
+        if args[i] == "--budget" {
+            limits.budget = Some(value.parse().map_err(|_| bad())?);
+        } else {
+            let secs = value.parse().map_err(|_| bad())?;
+            limits.timeout = Some(Duration::try_from_secs_f64(secs).map_err(|_| bad())?);
+        }
+        args.drain(i..i + 2);
+    }
+    Ok(limits)
+}
+
+/// Applies patches in order, growing the program with zeros if one is past
+/// its end. A patch past both the end and the dense memory limit is almost
+/// certainly a typo, so nothing is applied if there is one.
+pub fn apply_patches(prog: &mut Vec<i64>, patches: &[Patch]) -> Result<(), String> {
+    let limit = prog.len().max(DEFAULT_DENSE_LIMIT);
+    if let Some(p) = patches.iter().find(|p| p.addr >= limit) {
+        return Err(format!("Patch {} is past address limit {}", p, limit));
+    }
+    for p in patches {
+        if p.addr >= prog.len() {
+            prog.resize(p.addr + 1, 0);
+        }
+        prog[p.addr] = p.value;
+    }
+    Ok(())
+}
+
+/// One line saying which patches a run used, for its output.
+pub fn describe_patches(patches: &[Patch]) -> String {
+    if patches.is_empty() {
+        "Patches: none".to_string()
+    } else {
+        let list: Vec<String> = patches.iter().map(|p| p.to_string()).collect();
+        format!("Patches: {}", list.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::RunStatus;
+
+    #[test]
+    fn parses_with_whitespace() {
+        assert_eq!(parse_program("1,0,0,3,\n99\n"), vec![1, 0, 0, 3, 99]);
+        assert_eq!(parse_program("104, -1 ,99"), vec![104, -1, 99]);
+    }
+
+    #[test]
+    fn patches() {
+        let text = "# wake up\n0=2\n1=12, 2=-2 # noun, verb\n";
+        let patches = parse_patches(text).unwrap();
+        assert_eq!(describe_patches(&patches), "Patches: 0=2,1=12,2=-2");
+        assert_eq!(
+            parse_patches(describe_patches(&patches).trim_start_matches("Patches: ")),
+            Ok(patches.clone())
+        );
+
+        let mut prog = vec![1, 0, 0, 3, 99];
+        apply_patches(&mut prog, &patches).unwrap();
+        apply_patches(&mut prog, &[Patch { addr: 6, value: 7 }]).unwrap();
+        assert_eq!(prog, vec![2, 12, -2, 3, 99, 0, 7]);
+        let huge = [
+            Patch { addr: 0, value: 1 },
+            Patch {
+                addr: usize::MAX,
+                value: 1,
+            },
+        ];
+        assert_eq!(
+            apply_patches(&mut prog, &huge),
+            Err(format!(
+                "Patch {}=1 is past address limit 65536",
+                usize::MAX
+            ))
+        );
+        assert_eq!(prog, vec![2, 12, -2, 3, 99, 0, 7]);
+
+        assert_eq!(
+            parse_patches("1=12,2"),
+            Err("Bad patch \"2\", expected addr=value".to_string())
+        );
+        assert!(parse_patches("-1=0").is_err());
+        assert_eq!(describe_patches(&[]), "Patches: none");
+    }
+
+    #[test]
+    fn patch_args() {
+        let mut args: Vec<String> = [
+            "day", "input", "--patch", "1=12,2=2", "--debug", "--patch", "0=1",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let patches = take_patch_args(&mut args).unwrap();
+        assert_eq!(describe_patches(&patches), "Patches: 1=12,2=2,0=1");
+        assert_eq!(args, vec!["day", "input", "--debug"]);
+
+        let mut args = vec!["day".to_string(), "--patch".to_string()];
+        assert_eq!(
+            take_patch_args(&mut args),
+            Err("--patch needs an argument".to_string())
+        );
+    }
+
+    #[test]
+    fn limit_args() {
+        let mut args: Vec<String> = [
+            "day",
+            "input",
+            "--budget",
+            "100",
+            "--trace",
+            "t",
+            "--timeout",
+            "1.5",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let limits = take_limit_args(&mut args).unwrap();
+        assert_eq!(limits.budget, Some(100));
+        assert_eq!(limits.timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(args, vec!["day", "input", "--trace", "t"]);
+
+        let mut machine = Machine::new(vec![1105, 1, 0]);
+        limits.apply(&mut machine);
+        assert_eq!(machine.run_until_output(), Ok(RunStatus::BudgetExhausted));
+
+        for bad in &["-1", "soon"] {
+            let mut args = vec!["--timeout".to_string(), bad.to_string()];
+            assert_eq!(
+                take_limit_args(&mut args),
+                Err(format!("Bad --timeout {:?}", bad))
+            );
+        }
+    }
+}
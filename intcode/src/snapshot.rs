@@ -0,0 +1,213 @@
+//! Saved machine state, so a driver can checkpoint a run and resume it
+//! later instead of starting over from address 0.
+//!
+//! A snapshot file starts with the magic bytes `ICSN` and a version byte,
+//! followed by the program counter, relative base, the dense memory words,
+//! the queued input values and (since version 2) the nonzero words stored
+//! sparsely as address/value pairs, each list prefixed with its length.
+//! Version 3 adds the memory size limit: a 0 byte for none, or a 1 byte
+//! and the limit.
+
+use machine::ProcState;
+use memory::DEFAULT_MAX_SIZE;
+use std::fs;
+use varint::{put_int, put_uint, Reader};
+
+const MAGIC: &[u8; 4] = b"ICSN";
+const VERSION: u8 = 3;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snapshot {
+    /// Densely stored memory, starting at address 0.
+    pub memory: Vec<i64>,
+    /// Nonzero words above the dense region, in address order.
+    pub sparse: Vec<(usize, i64)>,
+    /// The machine's memory size limit, if it had one.
+    pub max_size: Option<usize>,
+    pub state: ProcState,
+    /// Input values queued on the machine but not read yet.
+    pub inputs: Vec<i64>,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        put_uint(&mut bytes, self.state.prog_count as u64);
+        put_int(&mut bytes, self.state.rel_base);
+        for list in &[&self.memory, &self.inputs] {
+            put_uint(&mut bytes, list.len() as u64);
+            for &x in list.iter() {
+                put_int(&mut bytes, x);
+            }
+        }
+        put_uint(&mut bytes, self.sparse.len() as u64);
+        for &(addr, value) in &self.sparse {
+            put_uint(&mut bytes, addr as u64);
+            put_int(&mut bytes, value);
+        }
+        match self.max_size {
+            Some(max_size) => {
+                bytes.push(1);
+                put_uint(&mut bytes, max_size as u64);
+            }
+            None => bytes.push(0),
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, String> {
+        if bytes.len() < 5 || &bytes[..4] != MAGIC {
+            return Err("Not a snapshot file".to_string());
+        }
+        let version = bytes[4];
+        if version == 0 || version > VERSION {
+            return Err(format!("Unsupported snapshot version: {}", bytes[4]));
+        }
+        let mut r = Reader::new(&bytes[5..]);
+        let state = ProcState {
+            prog_count: r.addr()?,
+            rel_base: r.int()?,
+        };
+        let mut read_list = || -> Result<Vec<i64>, String> {
+            let len = r.uint()?;
+            (0..len).map(|_| r.int()).collect()
+        };
+        let memory = read_list()?;
+        let inputs = read_list()?;
+        let mut sparse = Vec::new();
+        if version >= 2 {
+            for _ in 0..r.uint()? {
+                sparse.push((r.addr()?, r.int()?));
+            }
+        }
+        let max_size = if version >= 3 {
+            match r.byte()? {
+                0 => None,
+                1 => Some(r.addr()?),
+                b => return Err(format!("Bad memory limit flag: {}", b)),
+            }
+        } else {
+            // Older files don't say; keep the default unless the words
+            // saved show the machine had been allowed past it.
+            match sparse.last() {
+                Some(&(addr, _)) if addr >= DEFAULT_MAX_SIZE => None,
+                _ => Some(DEFAULT_MAX_SIZE),
+            }
+        };
+        if let Some(max_size) = max_size {
+            if let Some(&(addr, _)) = sparse.iter().find(|&&(addr, _)| addr >= max_size) {
+                return Err(format!(
+                    "Sparse word at {} beyond memory limit {}",
+                    addr, max_size
+                ));
+            }
+        }
+        if !r.at_end() {
+            return Err("Trailing data after snapshot".to_string());
+        }
+        Ok(Snapshot {
+            memory,
+            sparse,
+            max_size,
+            state,
+            inputs,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Snapshot, String> {
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Snapshot::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::{Machine, RunStatus};
+
+    #[test]
+    fn resumes_from_bytes() {
+        // Reads two values into rb-relative slots and prints their sum.
+        let prog = vec![109, 20, 203, 0, 203, 1, 22201, 0, 1, 2, 204, 2, 99];
+        let mut machine = Machine::new(prog);
+        machine.push_input(-40);
+        assert_eq!(machine.step(), Ok(None));
+        assert_eq!(machine.step(), Ok(None));
+        machine.push_input(2);
+        machine.push_input(9);
+
+        let bytes = machine.snapshot().to_bytes();
+        let mut restored = Machine::restore(Snapshot::from_bytes(&bytes).unwrap());
+        assert_eq!(restored.snapshot(), machine.snapshot());
+        assert_eq!(restored.run_until_output(), Ok(RunStatus::Output(-38)));
+        assert_eq!(restored.snapshot().inputs, vec![9]);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let snapshot = Machine::new(vec![99]).snapshot();
+        let mut bytes = snapshot.to_bytes();
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        bytes.push(0);
+        assert!(Snapshot::from_bytes(&bytes).is_err());
+        bytes[4] = 4;
+        assert_eq!(
+            Snapshot::from_bytes(&bytes),
+            Err("Unsupported snapshot version: 4".to_string())
+        );
+
+        // A crafted file with a word beyond its own limit.
+        let mut machine = Machine::new(vec![99]);
+        machine.memory_mut().set_max_size(Some(1 << 20));
+        *machine.memory_mut().get_mut(1 << 19).unwrap() = 7;
+        let mut snapshot = machine.snapshot();
+        snapshot.max_size = Some(1 << 18);
+        assert_eq!(
+            Snapshot::from_bytes(&snapshot.to_bytes()),
+            Err("Sparse word at 524288 beyond memory limit 262144".to_string())
+        );
+    }
+
+    #[test]
+    fn keeps_sparse_words() {
+        let mut machine = Machine::new(vec![1101, 5, 6, 1 << 30, 99]);
+        assert_eq!(machine.step(), Ok(None));
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.sparse, vec![(1 << 30, 11)]);
+        let restored = Machine::restore(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap());
+        assert_eq!(restored.read(1 << 30), Ok(11));
+    }
+
+    #[test]
+    fn keeps_memory_limit() {
+        let mut machine = Machine::new(vec![99]);
+        machine.memory_mut().set_max_size(None);
+        *machine.memory_mut().get_mut(1 << 40).unwrap() = 3;
+        let restored =
+            Machine::restore(Snapshot::from_bytes(&machine.snapshot().to_bytes()).unwrap());
+        assert_eq!(restored.memory().max_size(), None);
+        assert_eq!(restored.read(1 << 40), Ok(3));
+
+        let mut machine = Machine::new(vec![99]);
+        machine.memory_mut().set_max_size(Some(100));
+        let restored =
+            Machine::restore(Snapshot::from_bytes(&machine.snapshot().to_bytes()).unwrap());
+        assert_eq!(restored.memory().max_size(), Some(100));
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let bytes = [b'I', b'C', b'S', b'N', 1, 2, 1, 2, 198, 1, 0, 1, 10];
+        let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot.memory, vec![99, 0]);
+        assert_eq!(snapshot.inputs, vec![5]);
+        assert_eq!(snapshot.state.rel_base, -1);
+        assert!(snapshot.sparse.is_empty());
+        assert_eq!(snapshot.max_size, Some(DEFAULT_MAX_SIZE));
+    }
+}
@@ -0,0 +1,325 @@
+//! Execution traces: one record per executed instruction, packed with
+//! variable length integers so long runs stay small.
+//!
+//! A trace file starts with the magic bytes `ICTR` and a version byte.
+//! Each record is the program counter, relative base, opcode byte and the
+//! resolved operands of the instruction, followed by a flags byte saying
+//! which of the memory write, input value and output value come next.
+
+use instr::ParsedInstruction;
+use std::fmt;
+use std::fs;
+use varint::{put_int, put_uint, Reader};
+
+const MAGIC: &[u8; 4] = b"ICTR";
+const VERSION: u8 = 1;
+
+const HAS_WRITE: u8 = 1;
+const HAS_INPUT: u8 = 2;
+const HAS_OUTPUT: u8 = 4;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TraceRecord {
+    pub pc: usize,
+    pub rel_base: i64,
+    pub instr: ParsedInstruction,
+    /// Address and new value of the memory write, if any.
+    pub write: Option<(usize, i64)>,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>6} rb {} {:?}", self.pc, self.rel_base, self.instr)?;
+        if let Some((addr, value)) = self.write {
+            write!(f, " [{}] <- {}", addr, value)?;
+        }
+        if let Some(value) = self.input {
+            write!(f, " in {}", value)?;
+        }
+        if let Some(value) = self.output {
+            write!(f, " out {}", value)?;
+        }
+        Ok(())
+    }
+}
+
+fn put_instr(bytes: &mut Vec<u8>, instr: &ParsedInstruction) {
+    match *instr {
+        ParsedInstruction::Add { op1, op2, dest } => {
+            bytes.push(1);
+            put_int(bytes, op1);
+            put_int(bytes, op2);
+            put_uint(bytes, dest as u64);
+        }
+        ParsedInstruction::Multiply { op1, op2, dest } => {
+            bytes.push(2);
+            put_int(bytes, op1);
+            put_int(bytes, op2);
+            put_uint(bytes, dest as u64);
+        }
+        ParsedInstruction::Input { dest } => {
+            bytes.push(3);
+            put_uint(bytes, dest as u64);
+        }
+        ParsedInstruction::Output { out } => {
+            bytes.push(4);
+            put_int(bytes, out);
+        }
+        ParsedInstruction::JumpTrue { test, jump_dest } => {
+            bytes.push(5);
+            put_int(bytes, test);
+            put_uint(bytes, jump_dest as u64);
+        }
+        ParsedInstruction::JumpFalse { test, jump_dest } => {
+            bytes.push(6);
+            put_int(bytes, test);
+            put_uint(bytes, jump_dest as u64);
+        }
+        ParsedInstruction::LessThan { op1, op2, dest } => {
+            bytes.push(7);
+            put_int(bytes, op1);
+            put_int(bytes, op2);
+            put_uint(bytes, dest as u64);
+        }
+        ParsedInstruction::Equals { op1, op2, dest } => {
+            bytes.push(8);
+            put_int(bytes, op1);
+            put_int(bytes, op2);
+            put_uint(bytes, dest as u64);
+        }
+        ParsedInstruction::AdjustRelBase { adj } => {
+            bytes.push(9);
+            put_int(bytes, adj);
+        }
+        ParsedInstruction::Halt => bytes.push(99),
+    }
+}
+
+fn read_instr(r: &mut Reader) -> Result<ParsedInstruction, String> {
+    Ok(match r.byte()? {
+        1 => ParsedInstruction::Add {
+            op1: r.int()?,
+            op2: r.int()?,
+            dest: r.addr()?,
+        },
+        2 => ParsedInstruction::Multiply {
+            op1: r.int()?,
+            op2: r.int()?,
+            dest: r.addr()?,
+        },
+        3 => ParsedInstruction::Input { dest: r.addr()? },
+        4 => ParsedInstruction::Output { out: r.int()? },
+        5 => ParsedInstruction::JumpTrue {
+            test: r.int()?,
+            jump_dest: r.addr()?,
+        },
+        6 => ParsedInstruction::JumpFalse {
+            test: r.int()?,
+            jump_dest: r.addr()?,
+        },
+        7 => ParsedInstruction::LessThan {
+            op1: r.int()?,
+            op2: r.int()?,
+            dest: r.addr()?,
+        },
+        8 => ParsedInstruction::Equals {
+            op1: r.int()?,
+            op2: r.int()?,
+            dest: r.addr()?,
+        },
+        9 => ParsedInstruction::AdjustRelBase { adj: r.int()? },
+        99 => ParsedInstruction::Halt,
+        op => return Err(format!("Invalid Opcode in trace: {}", op)),
+    })
+}
+
+/// An encoded trace, recorded by a `Machine` or loaded from a file.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Trace {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Trace {
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+
+    /// Number of records.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, record: &TraceRecord) {
+        let bytes = &mut self.bytes;
+        put_uint(bytes, record.pc as u64);
+        put_int(bytes, record.rel_base);
+        put_instr(bytes, &record.instr);
+        let mut flags = 0;
+        if record.write.is_some() {
+            flags |= HAS_WRITE;
+        }
+        if record.input.is_some() {
+            flags |= HAS_INPUT;
+        }
+        if record.output.is_some() {
+            flags |= HAS_OUTPUT;
+        }
+        bytes.push(flags);
+        if let Some((addr, value)) = record.write {
+            put_uint(bytes, addr as u64);
+            put_int(bytes, value);
+        }
+        if let Some(value) = record.input {
+            put_int(bytes, value);
+        }
+        if let Some(value) = record.output {
+            put_int(bytes, value);
+        }
+        self.len += 1;
+    }
+
+    pub fn records(&self) -> Vec<TraceRecord> {
+        let mut r = Reader::new(&self.bytes);
+        (0..self.len)
+            .map(|_| read_record(&mut r).expect("Trace was encoded by push"))
+            .collect()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.bytes);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Trace, String> {
+        if bytes.len() < 5 || &bytes[..4] != MAGIC {
+            return Err("Not a trace file".to_string());
+        }
+        if bytes[4] != VERSION {
+            return Err(format!("Unsupported trace version: {}", bytes[4]));
+        }
+        let body = &bytes[5..];
+        let mut r = Reader::new(body);
+        let mut len = 0;
+        while !r.at_end() {
+            read_record(&mut r)?;
+            len += 1;
+        }
+        Ok(Trace {
+            bytes: body.to_vec(),
+            len,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Trace, String> {
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        Trace::from_bytes(&bytes)
+    }
+}
+
+fn read_record(r: &mut Reader) -> Result<TraceRecord, String> {
+    let pc = r.addr()?;
+    let rel_base = r.int()?;
+    let instr = read_instr(r)?;
+    let flags = r.byte()?;
+    let write = if flags & HAS_WRITE != 0 {
+        Some((r.addr()?, r.int()?))
+    } else {
+        None
+    };
+    let input = if flags & HAS_INPUT != 0 {
+        Some(r.int()?)
+    } else {
+        None
+    };
+    let output = if flags & HAS_OUTPUT != 0 {
+        Some(r.int()?)
+    } else {
+        None
+    };
+    Ok(TraceRecord {
+        pc,
+        rel_base,
+        instr,
+        write,
+        input,
+        output,
+    })
+}
+
+/// Index of the first record where the two runs differ. A run that stops
+/// early diverges at its length; identical traces give `None`.
+pub fn first_divergence(a: &[TraceRecord], b: &[TraceRecord]) -> Option<usize> {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(i) => Some(i),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine::{Machine, RunStatus};
+
+    fn traced(prog: &[i64], inputs: &[i64]) -> Trace {
+        let mut machine = Machine::new(prog.to_vec());
+        machine.start_trace();
+        for &x in inputs {
+            machine.push_input(x);
+        }
+        let status = machine.run_until_input(&mut Vec::new());
+        assert_eq!(status, Ok(RunStatus::Halted));
+        machine.take_trace().unwrap()
+    }
+
+    // Outputs 1 if the input is 8, 0 otherwise.
+    const EQ8: [i64; 11] = [3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+
+    #[test]
+    fn records_io_and_writes() {
+        let records = traced(&EQ8, &[8]).records();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].input, Some(8));
+        assert_eq!(records[0].write, Some((9, 8)));
+        assert_eq!(records[1].write, Some((9, 1)));
+        assert_eq!(records[2].output, Some(1));
+        assert_eq!(records[3].instr, ParsedInstruction::Halt);
+    }
+
+    #[test]
+    fn round_trips_bytes() {
+        let prog = vec![109, -5, 21101, -300, 1 << 40, 7, 204, 7, 99];
+        let trace = traced(&prog, &[]);
+        let loaded = Trace::from_bytes(&trace.to_bytes()).unwrap();
+        assert_eq!(loaded.records(), trace.records());
+        assert_eq!(loaded.records()[1].write, Some((2, (1 << 40) - 300)));
+
+        assert!(Trace::from_bytes(b"ICTR").is_err());
+        let mut truncated = trace.to_bytes();
+        truncated.pop();
+        assert!(Trace::from_bytes(&truncated).is_err());
+    }
+
+    #[test]
+    fn finds_divergence() {
+        let a = traced(&EQ8, &[8]).records();
+        let b = traced(&EQ8, &[7]).records();
+        assert_eq!(first_divergence(&a, &a), None);
+        assert_eq!(first_divergence(&a, &b), Some(0));
+        assert_eq!(first_divergence(&a[1..], &b[1..]), Some(0));
+        assert_eq!(first_divergence(&a, &a[..2]), Some(2));
+    }
+}
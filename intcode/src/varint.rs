@@ -0,0 +1,64 @@
+//! LEB128 style variable length integers used by the trace and snapshot
+//! file formats. Signed values are zigzag encoded first so small negative
+//! numbers stay short.
+
+pub fn put_uint(bytes: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        bytes.push((x as u8) | 0x80);
+        x >>= 7;
+    }
+    bytes.push(x as u8);
+}
+
+pub fn put_int(bytes: &mut Vec<u8>, x: i64) {
+    put_uint(bytes, ((x << 1) ^ (x >> 63)) as u64);
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub fn byte(&mut self) -> Result<u8, String> {
+        let b = *self
+            .bytes
+            .get(self.pos)
+            .ok_or_else(|| format!("Unexpected end of data at byte {}", self.pos))?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    pub fn uint(&mut self) -> Result<u64, String> {
+        let mut x = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            if shift > 63 {
+                return Err(format!("Oversized integer at byte {}", self.pos));
+            }
+            x |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(x);
+            }
+            shift += 7;
+        }
+    }
+
+    pub fn addr(&mut self) -> Result<usize, String> {
+        self.uint().map(|x| x as usize)
+    }
+
+    pub fn int(&mut self) -> Result<i64, String> {
+        let x = self.uint()?;
+        Ok(((x >> 1) as i64) ^ -((x & 1) as i64))
+    }
+}
@@ -0,0 +1,91 @@
+use memory::Memory;
+
+/// Mixes one memory word into a hash of the whole memory. Zero words add
+/// nothing, so a word that was never written and one set back to 0 hash
+/// the same.
+fn word_hash(addr: usize, value: i64) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+    // splitmix64 finaliser over the address and value.
+    let mut x = (addr as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ value as u64;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Spots a machine repeating an earlier state exactly, with no input or
+/// output since. Without I/O a machine is deterministic, so a repeated
+/// program counter, relative base and memory means it will go round the
+/// same cycle forever.
+///
+/// Memory is compared by a hash kept up to date on every write, so this
+/// costs a few operations per instruction whatever the memory size.
+/// States are checked with Brent's algorithm: one saved state, replaced
+/// after 1, 2, 4, ... instructions, finds any cycle within a couple of
+/// times its length.
+#[derive(Clone, Debug)]
+pub struct LoopDetector {
+    /// `None` when memory changed behind the detector's back and has to be
+    /// hashed from scratch.
+    mem_hash: Option<u64>,
+    saved: Option<(usize, i64, u64)>,
+    steps: u64,
+    power: u64,
+}
+
+impl LoopDetector {
+    pub fn new() -> LoopDetector {
+        LoopDetector {
+            mem_hash: None,
+            saved: None,
+            steps: 0,
+            power: 1,
+        }
+    }
+
+    /// Forgets the memory hash, e.g. after direct memory access.
+    pub fn invalidate(&mut self) {
+        self.mem_hash = None;
+        self.reset();
+    }
+
+    /// Forgets the saved state; I/O makes earlier states irrelevant.
+    pub fn reset(&mut self) {
+        self.saved = None;
+        self.steps = 0;
+        self.power = 1;
+    }
+
+    pub fn write(&mut self, addr: usize, old: i64, new: i64) {
+        if let Some(ref mut hash) = self.mem_hash {
+            *hash = hash
+                .wrapping_sub(word_hash(addr, old))
+                .wrapping_add(word_hash(addr, new));
+        }
+    }
+
+    /// Records the state after an instruction without I/O and returns
+    /// whether it has been seen before.
+    pub fn observe(&mut self, pc: usize, rel_base: i64, mem: &Memory) -> bool {
+        let mem_hash = *self.mem_hash.get_or_insert_with(|| {
+            let dense = mem.dense().iter().enumerate();
+            let sparse = mem.sparse_words();
+            dense
+                .map(|(a, &v)| word_hash(a, v))
+                .chain(sparse.iter().map(|&(a, v)| word_hash(a, v)))
+                .fold(0, u64::wrapping_add)
+        });
+        let state = (pc, rel_base, mem_hash);
+        if self.saved == Some(state) {
+            return true;
+        }
+        self.steps += 1;
+        if self.saved.is_none() || self.steps == self.power {
+            self.saved = Some(state);
+            self.steps = 0;
+            self.power *= 2;
+        }
+        false
+    }
+}